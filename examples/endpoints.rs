@@ -8,6 +8,7 @@ use std::env::var;
 use tokio::runtime::current_thread::Runtime;
 
 use binance::error::Result;
+use binance::model::KlineInterval;
 use binance::Binance;
 
 fn main() -> Result<()> {
@@ -70,7 +71,7 @@ fn main() -> Result<()> {
     println!("Get klines:");
     println!(
         "{:?}",
-        rt.block_on(bn.get_klines("BNBETH", "5m", 10, None, None)?)?
+        rt.block_on(bn.get_klines("BNBETH", KlineInterval::Minute5, 10, None, None)?)?
     );
 
     Ok(())
@@ -11,6 +11,7 @@ use tokio::runtime::current_thread::Runtime;
 
 use binance::error::Result;
 use binance::model::websocket::BinanceSubscription;
+use binance::model::KlineInterval;
 use binance::Binance;
 
 fn main() -> Result<()> {
@@ -34,7 +35,7 @@ fn main() -> Result<()> {
                 .and_then(|ws| {
                     ws.subscribe(BinanceSubscription::Candlestick(
                         "ethbtc".to_string(),
-                        "1m".to_string(),
+                        KlineInterval::Minute1,
                     ))
                 })
                 .and_then(|ws| ws.subscribe(BinanceSubscription::MiniTicker("adabtc".to_string())))
@@ -1,33 +1,28 @@
+use std::collections::BTreeSet;
+
 use failure::Error;
+use futures::future::join_all;
 use futures::Future;
-use serde_json::json;
-use std::collections::HashMap;
-use sugar::{convert_args, hashmap};
+use serde::de::DeserializeOwned;
+use serde_json::{from_value, json, Value};
 
 use client::Binance;
 use decimal::Decimal;
-use error::{BinanceError, Result};
+use error::{ApiErrorCode, BinanceError, Result};
+use model::order::{CancelReplaceOrder, NewOcoOrder, NewOrder, OrderResponse};
 use model::{
-    AccountInformationMsg, BalanceMsg, OrderCanceledMsg, OrderStatusMsg, TradeHistoryMsg,
-    TransactionMsg,
+    AccountInformationMsg, BalanceMsg, CancelReplaceMsg, CanceledOpenOrderMsg, OrderCanceledMsg,
+    OrderListMsg, OrderResponseType, OrderSide, OrderStatusMsg, TradeHistoryMsg, TransactionMsg,
 };
 
-static ORDER_TYPE_LIMIT: &'static str = "LIMIT";
-static ORDER_TYPE_MARKET: &'static str = "MARKET";
-static ORDER_SIDE_BUY: &'static str = "BUY";
-static ORDER_SIDE_SELL: &'static str = "SELL";
-static TIME_IN_FORCE_GTC: &'static str = "GTC";
-
 static API_V3_ORDER: &'static str = "/api/v3/order";
+static API_V3_ORDER_TEST: &'static str = "/api/v3/order/test";
+static API_V3_ORDER_OCO: &str = "/api/v3/order/oco";
+static API_V3_ORDER_CANCEL_REPLACE: &str = "/api/v3/order/cancelReplace";
+static API_V3_ORDER_LIST: &str = "/api/v3/orderList";
 
-struct OrderRequest {
-    pub symbol: String,
-    pub qty: Decimal,
-    pub price: Decimal,
-    pub order_side: String,
-    pub order_type: String,
-    pub time_in_force: String,
-}
+// Outcome of canceling the open orders of a symbol
+pub type SymbolCancel = (String, Result<Vec<CanceledOpenOrderMsg>>);
 
 impl Binance {
     // Account Information
@@ -90,47 +85,58 @@ impl Binance {
         Ok(order)
     }
 
+    pub fn order_status_by_client_id(
+        &self,
+        symbol: &str,
+        client_order_id: &str,
+    ) -> Result<impl Future<Item = OrderStatusMsg, Error = Error>> {
+        let params = json! {{"symbol": symbol, "origClientOrderId": client_order_id}};
+
+        let order = self.transport.signed_get(API_V3_ORDER, Some(params))?;
+        Ok(order)
+    }
+
+    // Place an order described by a `NewOrder`.
+    // Retried after transient failures only if it has a new_client_order_id, see `RetryPolicy`.
+    // The response type decides the newOrderRespType: `OrderAckMsg`, `OrderResultMsg` or `OrderFullMsg`.
+    pub fn place_order<R: OrderResponse>(
+        &self,
+        mut order: NewOrder,
+    ) -> Result<impl Future<Item = R, Error = Error>> {
+        order.new_order_resp_type = Some(R::RESPONSE_TYPE);
+        self.post_order(API_V3_ORDER, order)
+    }
+
+    // Test new order creation and signature/recvWindow.
+    // Validated by the exchange exactly like `place_order`, but never sent to the matching engine.
+    pub fn test_order(&self, order: NewOrder) -> Result<impl Future<Item = (), Error = Error>> {
+        Ok(self
+            .post_order(API_V3_ORDER_TEST, order)?
+            .map(|_: Value| ()))
+    }
+
     // Place a LIMIT order - BUY
+    // With an order validator set, `qty` and `price` are rounded to the symbol grid first
     pub fn limit_buy(
         &self,
         symbol: &str,
         qty: Decimal,
         price: Decimal,
     ) -> Result<impl Future<Item = TransactionMsg, Error = Error>> {
-        let buy: OrderRequest = OrderRequest {
-            symbol: symbol.into(),
-            qty: qty.into(),
-            price: price,
-            order_side: ORDER_SIDE_BUY.to_string(),
-            order_type: ORDER_TYPE_LIMIT.to_string(),
-            time_in_force: TIME_IN_FORCE_GTC.to_string(),
-        };
-        let params = self.build_order(buy);
-
-        let transaction = self.transport.signed_post(API_V3_ORDER, Some(params))?;
-
-        Ok(transaction)
+        let order = self.normalize_order(NewOrder::limit(symbol, OrderSide::Buy, qty, price));
+        self.place_order(order)
     }
 
     // Place a LIMIT order - SELL
+    // With an order validator set, `qty` and `price` are rounded to the symbol grid first
     pub fn limit_sell(
         &self,
         symbol: &str,
         qty: Decimal,
         price: Decimal,
     ) -> Result<impl Future<Item = TransactionMsg, Error = Error>> {
-        let sell: OrderRequest = OrderRequest {
-            symbol: symbol.into(),
-            qty: qty.into(),
-            price: price,
-            order_side: ORDER_SIDE_SELL.to_string(),
-            order_type: ORDER_TYPE_LIMIT.to_string(),
-            time_in_force: TIME_IN_FORCE_GTC.to_string(),
-        };
-        let params = self.build_order(sell);
-        let transaction = self.transport.signed_post(API_V3_ORDER, Some(params))?;
-
-        Ok(transaction)
+        let order = self.normalize_order(NewOrder::limit(symbol, OrderSide::Sell, qty, price));
+        self.place_order(order)
     }
 
     // Place a MARKET order - BUY
@@ -139,18 +145,8 @@ impl Binance {
         symbol: &str,
         qty: Decimal,
     ) -> Result<impl Future<Item = TransactionMsg, Error = Error>> {
-        let buy: OrderRequest = OrderRequest {
-            symbol: symbol.into(),
-            qty: qty,
-            price: 0.into(),
-            order_side: ORDER_SIDE_BUY.to_string(),
-            order_type: ORDER_TYPE_MARKET.to_string(),
-            time_in_force: TIME_IN_FORCE_GTC.to_string(),
-        };
-        let params = self.build_order(buy);
-        let transaction = self.transport.signed_post(API_V3_ORDER, Some(params))?;
-
-        Ok(transaction)
+        let order = self.normalize_order(NewOrder::market(symbol, OrderSide::Buy, qty));
+        self.place_order(order)
     }
 
     // Place a MARKET order - SELL
@@ -159,17 +155,8 @@ impl Binance {
         symbol: &str,
         qty: Decimal,
     ) -> Result<impl Future<Item = TransactionMsg, Error = Error>> {
-        let sell: OrderRequest = OrderRequest {
-            symbol: symbol.into(),
-            qty: qty,
-            price: 0.into(),
-            order_side: ORDER_SIDE_SELL.to_string(),
-            order_type: ORDER_TYPE_MARKET.to_string(),
-            time_in_force: TIME_IN_FORCE_GTC.to_string(),
-        };
-        let params = self.build_order(sell);
-        let transaction = self.transport.signed_post(API_V3_ORDER, Some(params))?;
-        Ok(transaction)
+        let order = self.normalize_order(NewOrder::market(symbol, OrderSide::Sell, qty));
+        self.place_order(order)
     }
 
     // Check an order's status
@@ -183,6 +170,170 @@ impl Binance {
         Ok(order_canceled)
     }
 
+    // Place an OCO order list, answered with the report of both orders
+    pub fn place_oco_order(
+        &self,
+        mut order: NewOcoOrder,
+    ) -> Result<impl Future<Item = OrderListMsg, Error = Error>> {
+        order.validate()?;
+        if let Some(ref validator) = self.order_validator {
+            validator.validate_oco(&order)?;
+        }
+        order.new_order_resp_type = Some(OrderResponseType::Result);
+        let order_list = self.transport.signed_post(API_V3_ORDER_OCO, Some(order))?;
+        Ok(order_list)
+    }
+
+    pub fn order_list(
+        &self,
+        order_list_id: i64,
+    ) -> Result<impl Future<Item = OrderListMsg, Error = Error>> {
+        let params = json! {{"orderListId": order_list_id}};
+        let order_list = self.transport.signed_get(API_V3_ORDER_LIST, Some(params))?;
+        Ok(order_list)
+    }
+
+    pub fn order_list_by_client_id(
+        &self,
+        list_client_order_id: &str,
+    ) -> Result<impl Future<Item = OrderListMsg, Error = Error>> {
+        let params = json! {{"origClientOrderId": list_client_order_id}};
+        let order_list = self.transport.signed_get(API_V3_ORDER_LIST, Some(params))?;
+        Ok(order_list)
+    }
+
+    // Cancel all the orders of a list
+    pub fn cancel_order_list(
+        &self,
+        symbol: &str,
+        order_list_id: i64,
+    ) -> Result<impl Future<Item = OrderListMsg, Error = Error>> {
+        let params = json! {{"symbol": symbol, "orderListId": order_list_id}};
+        let order_list = self
+            .transport
+            .signed_delete(API_V3_ORDER_LIST, Some(params))?;
+        Ok(order_list)
+    }
+
+    pub fn cancel_order_list_by_client_id(
+        &self,
+        symbol: &str,
+        list_client_order_id: &str,
+    ) -> Result<impl Future<Item = OrderListMsg, Error = Error>> {
+        let params = json! {{"symbol": symbol, "listClientOrderId": list_client_order_id}};
+        let order_list = self
+            .transport
+            .signed_delete(API_V3_ORDER_LIST, Some(params))?;
+        Ok(order_list)
+    }
+
+    // Order lists from `from_id`, or between `start_time` and `end_time`, at most `limit` (default 500)
+    pub fn all_order_lists(
+        &self,
+        from_id: Option<i64>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        limit: Option<u64>,
+    ) -> Result<impl Future<Item = Vec<OrderListMsg>, Error = Error>> {
+        let params = json! {{
+            "fromId": from_id,
+            "startTime": start_time,
+            "endTime": end_time,
+            "limit": limit,
+        }};
+        let order_lists = self
+            .transport
+            .signed_get("/api/v3/allOrderList", Some(params))?;
+        Ok(order_lists)
+    }
+
+    pub fn open_order_lists(&self) -> Result<impl Future<Item = Vec<OrderListMsg>, Error = Error>> {
+        let order_lists = self
+            .transport
+            .signed_get::<_, ()>("/api/v3/openOrderList", None)?;
+        Ok(order_lists)
+    }
+
+    pub fn cancel_order_by_client_id(
+        &self,
+        symbol: &str,
+        orig_client_order_id: &str,
+    ) -> Result<impl Future<Item = OrderCanceledMsg, Error = Error>> {
+        let params = json! {{"symbol": symbol, "origClientOrderId": orig_client_order_id}};
+        let order_canceled = self.transport.signed_delete(API_V3_ORDER, Some(params))?;
+        Ok(order_canceled)
+    }
+
+    // Cancel an order and place a new one in a single request.
+    // Partial and complete failures are also answered with the outcome of both sides,
+    // only other errors fail the future.
+    pub fn cancel_replace_order<R: OrderResponse>(
+        &self,
+        mut request: CancelReplaceOrder,
+    ) -> Result<impl Future<Item = CancelReplaceMsg<R>, Error = Error>> {
+        request.order.validate()?;
+        if let Some(ref validator) = self.order_validator {
+            validator.validate(&request.order)?;
+        }
+        request.order.new_order_resp_type = Some(R::RESPONSE_TYPE);
+
+        let replaced = self
+            .transport
+            .signed_post(API_V3_ORDER_CANCEL_REPLACE, Some(request))?
+            .or_else(|e: Error| {
+                let data = match e.downcast_ref::<BinanceError>() {
+                    Some(BinanceError::Api {
+                        code: ApiErrorCode::CancelReplacePartiallyFailed,
+                        data: Some(data),
+                        ..
+                    })
+                    | Some(BinanceError::Api {
+                        code: ApiErrorCode::CancelReplaceFailed,
+                        data: Some(data),
+                        ..
+                    }) => data.clone(),
+                    _ => return Err(e),
+                };
+                Ok(from_value(data)?)
+            });
+        Ok(replaced)
+    }
+
+    // Cancel all open orders and order lists on a symbol
+    pub fn cancel_open_orders(
+        &self,
+        symbol: &str,
+    ) -> Result<impl Future<Item = Vec<CanceledOpenOrderMsg>, Error = Error>> {
+        let params = json! {{"symbol": symbol}};
+        let canceled = self
+            .transport
+            .signed_delete("/api/v3/openOrders", Some(params))?;
+        Ok(canceled)
+    }
+
+    // Cancel the open orders of every symbol having some, e.g. as a kill switch.
+    // Every symbol is tried, the outcome of each is returned with it.
+    pub fn cancel_all_open_orders(
+        &self,
+    ) -> Result<impl Future<Item = Vec<SymbolCancel>, Error = Error>> {
+        let binance = self.clone();
+        let canceled = self
+            .get_open_orders_all()?
+            .and_then(move |orders| {
+                let symbols: BTreeSet<_> = orders.into_iter().map(|o| o.symbol).collect();
+                let cancels = symbols
+                    .into_iter()
+                    .map(|symbol| {
+                        let canceled = binance.cancel_open_orders(&symbol)?;
+                        Ok(canceled.then(|canceled| Ok((symbol, canceled))))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(join_all(cancels))
+            })
+            .flatten();
+        Ok(canceled)
+    }
+
     // Trade history
     pub fn trade_history(
         &self,
@@ -196,19 +347,193 @@ impl Binance {
         Ok(trade_history)
     }
 
-    fn build_order(&self, order: OrderRequest) -> HashMap<&'static str, String> {
-        let mut params: HashMap<&str, String> = convert_args!(hashmap!(
-            "symbol" => order.symbol,
-            "side" => order.order_side,
-            "type" => order.order_type,
-            "quantity" => order.qty.to_string(),
-        ));
+    fn post_order<O: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        order: NewOrder,
+    ) -> Result<impl Future<Item = O, Error = Error>> {
+        order.validate()?;
+        if let Some(ref validator) = self.order_validator {
+            validator.validate(&order)?;
+        }
+        let transaction = self.transport.signed_post(endpoint, Some(order))?;
+
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::runtime::Runtime;
+
+    use client::Binance;
+    use decimal::Decimal;
+    use error::{BinanceResponse, Result};
+    use model::order::{CancelReplaceOrder, NewOrder};
+    use model::{
+        CancelReplaceMode, CancelReplaceMsg, CancelReplaceResult, CanceledOpenOrderMsg,
+        OrderAckMsg, OrderSide,
+    };
+    use transport::test::serve;
+
+    static OPEN_ORDERS: &str = r#"[
+        {"symbol":"BNBBTC","orderId":1,"clientOrderId":"a","price":"0.1","origQty":"1",
+         "executedQty":"0","cummulativeQuoteQty":"0","status":"NEW","timeInForce":"GTC",
+         "type":"LIMIT","side":"BUY","stopPrice":"0","icebergQty":"0","time":1499827319559,
+         "updateTime":1499827319559,"isWorking":true},
+        {"symbol":"ETHBTC","orderId":2,"clientOrderId":"b","price":"0.02","origQty":"1",
+         "executedQty":"0","cummulativeQuoteQty":"0","status":"NEW","timeInForce":"GTC",
+         "type":"LIMIT","side":"SELL","stopPrice":"0","icebergQty":"0","time":1499827319559,
+         "updateTime":1499827319559,"isWorking":true},
+        {"symbol":"BNBBTC","orderId":3,"clientOrderId":"c","price":"0.1","origQty":"1",
+         "executedQty":"0","cummulativeQuoteQty":"0","status":"NEW","timeInForce":"GTC",
+         "type":"LIMIT","side":"BUY","stopPrice":"0","icebergQty":"0","time":1499827319559,
+         "updateTime":1499827319559,"isWorking":true}]"#;
+
+    // A canceled order and a canceled OCO order list
+    static CANCELED_OPEN_ORDERS: &str = r#"[
+        {"symbol":"BTCUSDT","origClientOrderId":"E6APeyTJvkMvLMYMqu1KQ4","orderId":11,
+         "orderListId":-1,"clientOrderId":"pXLV6Hz6mprAcVYpVMTGgx","price":"0.089853",
+         "origQty":"0.178622","executedQty":"0.000000","cummulativeQuoteQty":"0.000000",
+         "status":"CANCELED","timeInForce":"GTC","type":"LIMIT","side":"BUY"},
+        {"orderListId":1929,"contingencyType":"OCO","listStatusType":"ALL_DONE",
+         "listOrderStatus":"ALL_DONE","listClientOrderId":"2inzWQdDvZLHbbAmAozX2N",
+         "transactionTime":1585230948299,"symbol":"BTCUSDT",
+         "orders":[{"symbol":"BTCUSDT","orderId":20,"clientOrderId":"CwOOIPHSmYywx6jZX77TdL"},
+                   {"symbol":"BTCUSDT","orderId":21,"clientOrderId":"461cPg51vQjV3zIMOXNz39"}],
+         "orderReports":[
+            {"symbol":"BTCUSDT","origClientOrderId":"CwOOIPHSmYywx6jZX77TdL","orderId":20,
+             "orderListId":1929,"clientOrderId":"pXLV6Hz6mprAcVYpVMTGgx","transactTime":1585230948299,
+             "price":"1.00000000","origQty":"10.00000000","executedQty":"0.00000000",
+             "cummulativeQuoteQty":"0.00000000","status":"CANCELED","timeInForce":"GTC",
+             "type":"STOP_LOSS_LIMIT","side":"BUY","stopPrice":"1.00000000"},
+            {"symbol":"BTCUSDT","origClientOrderId":"461cPg51vQjV3zIMOXNz39","orderId":21,
+             "orderListId":1929,"clientOrderId":"pXLV6Hz6mprAcVYpVMTGgx","transactTime":1585230948299,
+             "price":"3.00000000","origQty":"10.00000000","executedQty":"0.00000000",
+             "cummulativeQuoteQty":"0.00000000","status":"CANCELED","timeInForce":"GTC",
+             "type":"LIMIT_MAKER","side":"BUY"}]}]"#;
+
+    #[test]
+    fn cancel_order_by_client_id() -> Result<()> {
+        let mut rt = Runtime::new()?;
+        let (config, log) = serve(
+            &mut rt,
+            vec![(
+                200,
+                r#"{"symbol":"BNBBTC","origClientOrderId":"my order","orderId":1,
+                    "orderListId":-1,"clientOrderId":"cancel"}"#,
+            )],
+        );
+        let binance = Binance::with_credential_and_config("key", "secret", config);
 
-        if order.price != 0.into() {
-            params.insert("price", order.price.to_string());
-            params.insert("timeInForce", order.time_in_force.to_string());
+        let canceled = rt.block_on(binance.cancel_order_by_client_id("BNBBTC", "my order")?)?;
+        assert_eq!(canceled.order_id, 1);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["DELETE /api/v3/order origClientOrderId=my order&symbol=BNBBTC"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_open_orders() -> Result<()> {
+        let mut rt = Runtime::new()?;
+        let (config, log) = serve(&mut rt, vec![(200, CANCELED_OPEN_ORDERS)]);
+        let binance = Binance::with_credential_and_config("key", "secret", config);
+
+        let canceled = rt.block_on(binance.cancel_open_orders("BTCUSDT")?)?;
+        match canceled[..] {
+            [CanceledOpenOrderMsg::Order(ref order), CanceledOpenOrderMsg::OrderList(ref list)] => {
+                assert_eq!(order.order_id, 11);
+                assert_eq!(list.order_list_id, 1929);
+                assert_eq!(list.order_reports.len(), 2);
+            }
+            _ => panic!("unexpected cancels {:?}", canceled),
         }
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["DELETE /api/v3/openOrders symbol=BTCUSDT"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_all_open_orders() -> Result<()> {
+        let mut rt = Runtime::new()?;
+        let (config, log) = serve(
+            &mut rt,
+            vec![
+                (200, OPEN_ORDERS),
+                (200, CANCELED_OPEN_ORDERS),
+                (400, r#"{"code":-2011,"msg":"Unknown order sent."}"#),
+            ],
+        );
+        let binance = Binance::with_credential_and_config("key", "secret", config);
+
+        // A failed symbol does not discard the cancels of the others
+        let canceled = rt.block_on(binance.cancel_all_open_orders()?)?;
+        let symbols: Vec<_> = canceled.iter().map(|(symbol, _)| symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["BNBBTC", "ETHBTC"]);
+        let (ok, failed): (Vec<_>, Vec<_>) = canceled.into_iter().partition(|(_, r)| r.is_ok());
+        assert_eq!(ok.len(), 1);
+        assert_eq!(ok[0].1.as_ref().unwrap().len(), 2);
+        assert_eq!(failed.len(), 1);
 
-        params
+        // One cancel per symbol, sent concurrently
+        let mut requests = log.lock().unwrap().clone();
+        requests[1..].sort();
+        assert_eq!(
+            requests,
+            vec![
+                "GET /api/v3/openOrders",
+                "DELETE /api/v3/openOrders symbol=BNBBTC",
+                "DELETE /api/v3/openOrders symbol=ETHBTC",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_replace_order_new_order_failed() -> Result<()> {
+        let mut rt = Runtime::new()?;
+        let (config, log) = serve(
+            &mut rt,
+            vec![(
+                400,
+                r#"{"code":-2022,"msg":"Order cancel-replace failed.","data":{
+                    "cancelResult":"SUCCESS","newOrderResult":"FAILURE",
+                    "cancelResponse":{"symbol":"BTCUSDT","origClientOrderId":"86M8erehfExV8z2RC8Zo8k",
+                        "orderId":3,"orderListId":-1,"clientOrderId":"G1kLo6aDv2KGNTFcjfTSFq",
+                        "price":"0.006123","origQty":"10000.000000","executedQty":"0.000000",
+                        "cummulativeQuoteQty":"0.000000","status":"CANCELED","timeInForce":"GTC",
+                        "type":"LIMIT_MAKER","side":"SELL"},
+                    "newOrderResponse":{"code":-2010,"msg":"Order would immediately match and take."}}}"#,
+            )],
+        );
+        let binance = Binance::with_credential_and_config("key", "secret", config);
+
+        let order = NewOrder::limit(
+            "BTCUSDT",
+            OrderSide::Sell,
+            Decimal::new(10000, 0),
+            Decimal::new(6000, 6),
+        );
+        let request = CancelReplaceOrder::by_order_id(order, CancelReplaceMode::StopOnFailure, 3);
+        let replaced: CancelReplaceMsg<OrderAckMsg> =
+            rt.block_on(binance.cancel_replace_order(request)?)?;
+
+        // The failed half is decoded instead of failing the whole request
+        assert_eq!(replaced.cancel_result, CancelReplaceResult::Success);
+        assert_eq!(replaced.new_order_result, CancelReplaceResult::Failure);
+        match replaced.cancel_response {
+            Some(BinanceResponse::Success(ref canceled)) => assert_eq!(canceled.order_id, 3),
+            ref r => panic!("unexpected cancel response {:?}", r),
+        }
+        match replaced.new_order_response {
+            Some(BinanceResponse::Error(ref e)) => assert_eq!(e.code, -2010),
+            ref r => panic!("unexpected new order response {:?}", r),
+        }
+        assert!(log.lock().unwrap()[0].starts_with("POST /api/v3/order/cancelReplace"));
+        Ok(())
     }
 }
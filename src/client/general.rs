@@ -11,16 +11,33 @@ impl Binance {
     pub fn ping(&self) -> Result<impl Future<Item = String, Error = Error>> {
         Ok(self
             .transport
-            .get::<_, ()>("/api/v1/ping", None)?
+            .get::<_, ()>("/api/v3/ping", None)?
             .map(|_: Value| "pong".into()))
     }
 
     // Check server time
     pub fn get_server_time(&self) -> Result<impl Future<Item = ServerTimeMsg, Error = Error>> {
-        Ok(self.transport.get::<_, ()>("/api/v1/time", None)?)
+        Ok(self.transport.get::<_, ()>("/api/v3/time", None)?)
     }
 
+    // Measures and stores the server clock offset applied to signed requests, in ms
+    pub fn sync_time(&self) -> Result<impl Future<Item = i64, Error = Error>> {
+        self.transport.sync_time()
+    }
+
+    pub fn time_offset(&self) -> i64 {
+        self.transport.time_offset()
+    }
+
+    // Also seeds the rate limiter with the announced limits
     pub fn get_exchange_info(&self) -> Result<impl Future<Item = ExchangeInfoMsg, Error = Error>> {
-        Ok(self.transport.get::<_, ()>("/api/v1/exchangeInfo", None)?)
+        let rate_limiter = self.transport.rate_limiter().clone();
+        Ok(self
+            .transport
+            .get::<_, ()>("/api/v3/exchangeInfo", None)?
+            .map(move |info: ExchangeInfoMsg| {
+                rate_limiter.seed(&info.rate_limits);
+                info
+            }))
     }
 }
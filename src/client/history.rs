@@ -0,0 +1,839 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::Error;
+use futures::{Async, Future, Poll, Stream};
+use serde_json::json;
+
+use client::Binance;
+use error::Result;
+use model::websocket::AggregateTrade;
+use model::{KlineInterval, KlineMsg, OrderStatusMsg, TradeHistoryMsg};
+
+// Largest page of allOrders, myTrades and aggTrades
+static PAGE_LIMIT: u64 = 1000;
+
+// aggTrades rejects time ranges of an hour or more
+static AGG_TRADES_WINDOW: u64 = 3_600_000;
+
+// allOrders and myTrades limit time ranges to 24 hours, also when only one bound is given
+static DAY_WINDOW: u64 = 86_400_000;
+
+// Page of an history query, `from_id` being an order id for allOrders and a trade id for myTrades
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub from_id: Option<i64>,
+    pub start_time: Option<u64>,
+    pub end_time: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryDirection {
+    // Oldest first, from the start time
+    Forward,
+    // Newest first, from the end time
+    Backward,
+}
+
+// Record of an history endpoint
+pub trait HistoryItem {
+    fn id(&self) -> i64;
+    fn time(&self) -> u64;
+}
+
+impl HistoryItem for OrderStatusMsg {
+    fn id(&self) -> i64 {
+        self.order_id
+    }
+
+    fn time(&self) -> u64 {
+        self.time
+    }
+}
+
+impl HistoryItem for TradeHistoryMsg {
+    fn id(&self) -> i64 {
+        self.id as i64
+    }
+
+    fn time(&self) -> u64 {
+        self.time
+    }
+}
+
+impl HistoryItem for AggregateTrade {
+    fn id(&self) -> i64 {
+        self.aggregate_trade_id
+    }
+
+    fn time(&self) -> u64 {
+        self.trade_time
+    }
+}
+
+impl Binance {
+    // One page of the orders of a symbol, in all states
+    pub fn all_orders(
+        &self,
+        symbol: &str,
+        query: HistoryQuery,
+    ) -> Result<impl Future<Item = Vec<OrderStatusMsg>, Error = Error>> {
+        let params = json! {{
+            "symbol": symbol,
+            "orderId": query.from_id,
+            "startTime": query.start_time,
+            "endTime": query.end_time,
+            "limit": query.limit,
+        }};
+        let orders = self
+            .transport
+            .signed_get("/api/v3/allOrders", Some(params))?;
+        Ok(orders)
+    }
+
+    // One page of the trades of a symbol
+    pub fn my_trades(
+        &self,
+        symbol: &str,
+        query: HistoryQuery,
+    ) -> Result<impl Future<Item = Vec<TradeHistoryMsg>, Error = Error>> {
+        let params = json! {{
+            "symbol": symbol,
+            "fromId": query.from_id,
+            "startTime": query.start_time,
+            "endTime": query.end_time,
+            "limit": query.limit,
+        }};
+        let trades = self
+            .transport
+            .signed_get("/api/v3/myTrades", Some(params))?;
+        Ok(trades)
+    }
+
+    // Every order of a symbol between `start_time` and `end_time`, fetched page by page
+    pub fn all_orders_history(
+        &self,
+        symbol: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        direction: HistoryDirection,
+    ) -> History<OrderStatusMsg> {
+        let binance = self.clone();
+        let symbol = symbol.to_string();
+        History::new(start_time, end_time, direction, DAY_WINDOW, move |query| {
+            Ok(Box::new(binance.all_orders(&symbol, query)?))
+        })
+    }
+
+    // Every trade of a symbol between `start_time` and `end_time`, fetched page by page
+    pub fn my_trades_history(
+        &self,
+        symbol: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        direction: HistoryDirection,
+    ) -> History<TradeHistoryMsg> {
+        let binance = self.clone();
+        let symbol = symbol.to_string();
+        History::new(start_time, end_time, direction, DAY_WINDOW, move |query| {
+            Ok(Box::new(binance.my_trades(&symbol, query)?))
+        })
+    }
+
+    // Complete aggregate trade tape of a symbol between `start_time` and `end_time`, oldest first.
+    // Hours without trades are skipped until the first trade, which is then followed by id.
+    pub fn agg_trades_history(
+        &self,
+        symbol: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    ) -> History<AggregateTrade> {
+        let binance = self.clone();
+        let symbol = symbol.to_string();
+        History::new(
+            start_time,
+            end_time,
+            HistoryDirection::Forward,
+            AGG_TRADES_WINDOW,
+            move |query| Ok(Box::new(binance.get_agg_trades(&symbol, query)?)),
+        )
+    }
+
+    // Klines of a symbol opened from `start_time` until `end_time`, or until the latest one
+    pub fn kline_history(
+        &self,
+        symbol: &str,
+        interval: KlineInterval,
+        start_time: u64,
+        end_time: Option<u64>,
+    ) -> KlineHistory {
+        let binance = self.clone();
+        let symbol = symbol.to_string();
+        KlineHistory::new(interval, start_time, end_time, move |start| {
+            let klines = binance.get_klines(&symbol, interval, PAGE_LIMIT, start, end_time)?;
+            Ok(Box::new(klines))
+        })
+    }
+}
+
+type Page<T> = Box<dyn Future<Item = Vec<T>, Error = Error> + Send>;
+
+// Stream walking an history endpoint until the time range is exhausted.
+// Pages are fetched one at a time, when the previous one has been consumed.
+pub struct History<T> {
+    fetch: Box<dyn Fn(HistoryQuery) -> Result<Page<T>> + Send>,
+    start_time: Option<u64>,
+    end_time: Option<u64>,
+    direction: HistoryDirection,
+    // Length of the time windows of endpoints rejecting longer or open ranges.
+    // They are walked forward until the first record, and backward until the start time.
+    window: u64,
+    // Time range of the window walked backward, and its records yielded once it is complete
+    window_range: Option<(u64, u64)>,
+    window_records: Vec<T>,
+    // Last fetched query
+    query: HistoryQuery,
+    // `None` once the range is exhausted
+    next: Option<HistoryQuery>,
+    page: Option<Page<T>>,
+    buffer: VecDeque<T>,
+}
+
+impl<T: HistoryItem> History<T> {
+    fn new<F>(
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        direction: HistoryDirection,
+        window: u64,
+        fetch: F,
+    ) -> Self
+    where
+        F: Fn(HistoryQuery) -> Result<Page<T>> + Send + 'static,
+    {
+        let mut history = History {
+            fetch: Box::new(fetch),
+            start_time,
+            end_time,
+            direction,
+            window,
+            window_range: None,
+            window_records: vec![],
+            query: HistoryQuery::default(),
+            next: None,
+            page: None,
+            buffer: VecDeque::new(),
+        };
+        history.next = match (direction, start_time) {
+            (HistoryDirection::Forward, Some(start)) => Some(history.window_from(start)),
+            // The range starts at the first record
+            (HistoryDirection::Forward, None) => Some(HistoryQuery {
+                from_id: Some(0),
+                start_time: None,
+                end_time: None,
+                limit: Some(PAGE_LIMIT),
+            }),
+            (HistoryDirection::Backward, Some(_)) => {
+                let end = *history.end_time.get_or_insert_with(now);
+                history.window_to(end)
+            }
+            // The range starts at the oldest record, looked up first
+            (HistoryDirection::Backward, None) => {
+                history.end_time.get_or_insert_with(now);
+                Some(HistoryQuery {
+                    from_id: Some(0),
+                    start_time: None,
+                    end_time: None,
+                    limit: Some(1),
+                })
+            }
+        };
+        history
+    }
+
+    fn window_from(&self, start: u64) -> HistoryQuery {
+        HistoryQuery {
+            from_id: None,
+            start_time: Some(start),
+            end_time: Some(start + self.window - 1),
+            limit: Some(PAGE_LIMIT),
+        }
+    }
+
+    // Window ending at `end`, cut at the start time
+    fn window_to(&mut self, end: u64) -> Option<HistoryQuery> {
+        let start_time = self.start_time.unwrap_or(0);
+        if end < start_time {
+            return None;
+        }
+        let start = (end + 1).saturating_sub(self.window).max(start_time);
+        self.window_range = Some((start, end));
+        Some(HistoryQuery {
+            from_id: None,
+            start_time: Some(start),
+            end_time: Some(end),
+            limit: Some(PAGE_LIMIT),
+        })
+    }
+
+    fn in_range(&self, item: &T) -> bool {
+        item.time() >= self.start_time.unwrap_or(0)
+            && item.time() <= self.end_time.unwrap_or(u64::MAX)
+    }
+
+    // Buffers the records of a page in the walking order and prepares the next query
+    fn consume(&mut self, mut page: Vec<T>) {
+        let full = page.len() as u64 >= PAGE_LIMIT;
+        page.sort_by_key(|item| item.id());
+
+        match self.direction {
+            HistoryDirection::Forward => {
+                let past_end =
+                    page.last().map_or(0, HistoryItem::time) > self.end_time.unwrap_or(u64::MAX);
+                let windowed = self.query.from_id.is_none();
+                self.next = match page.last() {
+                    // A window may end before the next page, the following records are fetched by id
+                    Some(last) if (full || windowed) && !past_end => Some(HistoryQuery {
+                        from_id: Some(last.id() + 1),
+                        start_time: None,
+                        end_time: None,
+                        limit: Some(PAGE_LIMIT),
+                    }),
+                    Some(_) => None,
+                    None => match self.query.end_time {
+                        Some(window_end) if windowed => {
+                            let end = self.end_time.unwrap_or_else(now);
+                            if window_end < end {
+                                Some(self.window_from(window_end + 1))
+                            } else {
+                                None
+                            }
+                        }
+                        _ => None,
+                    },
+                };
+                let page: Vec<_> = page.into_iter().filter(|i| self.in_range(i)).collect();
+                self.buffer.extend(page);
+            }
+            HistoryDirection::Backward => self.consume_window(page),
+        }
+    }
+
+    // Walks a window forward like a whole range, then yields its records newest first
+    // and moves to the previous window
+    fn consume_window(&mut self, page: Vec<T>) {
+        let (window_start, window_end) = match self.window_range {
+            Some(range) => range,
+            // Oldest record
+            None => {
+                self.start_time = page.first().map(HistoryItem::time);
+                self.next = match (self.start_time, self.end_time) {
+                    (Some(_), Some(end)) => self.window_to(end),
+                    _ => None,
+                };
+                return;
+            }
+        };
+
+        let full = page.len() as u64 >= PAGE_LIMIT;
+        let past_end = page.last().map_or(0, HistoryItem::time) > window_end;
+        let last_id = page.last().map(HistoryItem::id);
+        let page: Vec<_> = page
+            .into_iter()
+            .filter(|i| i.time() <= window_end && self.in_range(i))
+            .collect();
+        self.window_records.extend(page);
+
+        self.next = match last_id {
+            Some(last_id) if full && !past_end => Some(HistoryQuery {
+                from_id: Some(last_id + 1),
+                start_time: None,
+                end_time: None,
+                limit: Some(PAGE_LIMIT),
+            }),
+            _ => {
+                self.buffer.extend(self.window_records.drain(..).rev());
+                if window_start > self.start_time.unwrap_or(0) {
+                    self.window_to(window_start - 1)
+                } else {
+                    None
+                }
+            }
+        };
+    }
+}
+
+impl<T: HistoryItem> Stream for History<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(item) = self.buffer.pop_front() {
+                return Ok(Async::Ready(Some(item)));
+            }
+
+            if let Some(mut page) = self.page.take() {
+                match page.poll()? {
+                    Async::Ready(page) => {
+                        self.consume(page);
+                        continue;
+                    }
+                    Async::NotReady => {
+                        self.page = Some(page);
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+
+            match self.next.take() {
+                Some(query) => {
+                    trace!("[History] Fetching {:?}", query);
+                    self.query = query;
+                    self.page = Some((self.fetch)(query)?);
+                }
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+// Klines missing between two consecutive ones, e.g. during an exchange maintenance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KlineGap {
+    // Open time of the first missing kline
+    pub from: u64,
+    // Open time of the kline following the last missing one
+    pub to: u64,
+}
+
+// Stream paging through the klines of a time range, oldest first.
+// Open times are checked to be contiguous, the gaps found so far are listed by `gaps`.
+pub struct KlineHistory {
+    fetch: Box<dyn Fn(u64) -> Result<Page<KlineMsg>> + Send>,
+    interval: KlineInterval,
+    end_time: Option<u64>,
+    // Open time of the next kline
+    expected: u64,
+    gaps: Vec<KlineGap>,
+    // Start time of the next page, `None` once the range is exhausted
+    next: Option<u64>,
+    page: Option<Page<KlineMsg>>,
+    buffer: VecDeque<KlineMsg>,
+}
+
+impl KlineHistory {
+    fn new<F>(interval: KlineInterval, start_time: u64, end_time: Option<u64>, fetch: F) -> Self
+    where
+        F: Fn(u64) -> Result<Page<KlineMsg>> + Send + 'static,
+    {
+        // First kline opened from the start time, a gap being reported if it is missing
+        let first = interval.open_time(start_time);
+        let first = if first < start_time {
+            interval.next_open_time(first)
+        } else {
+            first
+        };
+        KlineHistory {
+            fetch: Box::new(fetch),
+            interval,
+            end_time,
+            expected: first,
+            gaps: vec![],
+            next: Some(start_time),
+            page: None,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    // Continues after the last stored kline, a gap being reported if the next one is missing
+    pub fn resume_from(mut self, last_open_time: u64) -> Self {
+        let next = self.interval.next_open_time(last_open_time);
+        self.expected = next;
+        self.next = Some(next);
+        self
+    }
+
+    pub fn gaps(&self) -> &[KlineGap] {
+        &self.gaps
+    }
+
+    fn consume(&mut self, page: Vec<KlineMsg>) {
+        let full = page.len() as u64 >= PAGE_LIMIT;
+        for kline in page {
+            if kline.open_time < self.expected {
+                continue;
+            }
+            if kline.open_time > self.expected {
+                self.gap(self.expected, kline.open_time);
+            }
+            self.expected = self.interval.next_open_time(kline.open_time);
+            self.buffer.push_back(kline);
+        }
+
+        self.next = if full && self.expected <= self.end_time.unwrap_or(u64::MAX) {
+            Some(self.expected)
+        } else {
+            None
+        };
+
+        // Klines missing at the end of the range
+        if let (None, Some(end)) = (self.next, self.end_time) {
+            let last = self.interval.open_time(end.min(now()));
+            if self.expected <= last {
+                let to = self.interval.next_open_time(last);
+                self.gap(self.expected, to);
+            }
+        }
+    }
+
+    fn gap(&mut self, from: u64, to: u64) {
+        let gap = KlineGap { from, to };
+        warn!("[KlineHistory] Missing {} klines {:?}", self.interval, gap);
+        self.gaps.push(gap);
+    }
+}
+
+impl Stream for KlineHistory {
+    type Item = KlineMsg;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(kline) = self.buffer.pop_front() {
+                return Ok(Async::Ready(Some(kline)));
+            }
+
+            if let Some(mut page) = self.page.take() {
+                match page.poll()? {
+                    Async::Ready(page) => {
+                        self.consume(page);
+                        continue;
+                    }
+                    Async::NotReady => {
+                        self.page = Some(page);
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+
+            match self.next.take() {
+                Some(start) => {
+                    trace!(
+                        "[KlineHistory] Fetching {} klines from {}",
+                        self.interval,
+                        start
+                    );
+                    self.page = Some((self.fetch)(start)?);
+                }
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() * 1000 + u64::from(now.subsec_millis())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::ok;
+    use futures::{Future, Stream};
+
+    use super::{
+        History, HistoryDirection, HistoryItem, HistoryQuery, KlineGap, KlineHistory, Page,
+        PAGE_LIMIT,
+    };
+    use decimal::Decimal;
+    use model::{KlineInterval, KlineMsg};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Record(i64, u64);
+
+    impl HistoryItem for Record {
+        fn id(&self) -> i64 {
+            self.0
+        }
+
+        fn time(&self) -> u64 {
+            self.1
+        }
+    }
+
+    // Records with ids 0..n at time offset + 10 * id, served like allOrders
+    fn history(
+        n: i64,
+        offset: u64,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        direction: HistoryDirection,
+        window: u64,
+    ) -> (History<Record>, Arc<Mutex<Vec<HistoryQuery>>>) {
+        let queries = Arc::new(Mutex::new(vec![]));
+        let log = queries.clone();
+        let fetch = move |q: HistoryQuery| {
+            log.lock().unwrap().push(q);
+            let limit = q.limit.unwrap() as usize;
+            let records = (0..n).map(|i| Record(i, offset + 10 * i as u64));
+            let page: Vec<_> = match (q.from_id, q.start_time, q.end_time) {
+                (Some(id), _, _) => records.filter(|r| r.0 >= id).take(limit).collect(),
+                (None, Some(start), Some(end)) => records
+                    .filter(|r| r.1 >= start && r.1 <= end)
+                    .take(limit)
+                    .collect(),
+                _ => Err(format_err!("Invalid query {:?}", q))?,
+            };
+            Ok(Box::new(ok(page)) as Page<Record>)
+        };
+        let history = History::new(start_time, end_time, direction, window, fetch);
+        (history, queries)
+    }
+
+    #[test]
+    fn forward() {
+        let n = PAGE_LIMIT as i64 * 2 + 10;
+        let (history, queries) = history(
+            n,
+            0,
+            Some(50),
+            Some(10 * 1500),
+            HistoryDirection::Forward,
+            DAY,
+        );
+        let records = history.collect().wait().unwrap();
+        assert_eq!(records.first(), Some(&Record(5, 50)));
+        assert_eq!(records.last(), Some(&Record(1500, 15000)));
+        assert_eq!(records.len(), 1496);
+        assert_eq!(queries.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn backward() {
+        // Windows of 1500 records, more than a page
+        let n = PAGE_LIMIT as i64 * 2 + 10;
+        let end_time = 10 * n as u64;
+        let (history, queries) = history(
+            n,
+            0,
+            None,
+            Some(end_time),
+            HistoryDirection::Backward,
+            15_000,
+        );
+        let records = history.collect().wait().unwrap();
+        assert_eq!(records.len(), n as usize);
+        assert_eq!(records.first(), Some(&Record(n - 1, 10 * (n - 1) as u64)));
+        assert_eq!(records.last(), Some(&Record(0, 0)));
+        assert!(records.windows(2).all(|w| w[0].0 == w[1].0 + 1));
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries[1].start_time, Some(end_time - 15_000 + 1));
+        assert_eq!(queries[2].from_id, Some(PAGE_LIMIT as i64 + 511));
+    }
+
+    #[test]
+    fn backward_same_ms() {
+        // More than a page of records at the same ms, preceded by a few older ones
+        let records: Vec<_> = (0..1110)
+            .map(|i| Record(i, if i < 10 { 10 } else { 100 }))
+            .collect();
+        let fetch = move |q: HistoryQuery| {
+            let limit = q.limit.unwrap() as usize;
+            let page: Vec<_> = match (q.from_id, q.start_time, q.end_time) {
+                (Some(id), None, None) => records
+                    .iter()
+                    .filter(|r| r.0 >= id)
+                    .take(limit)
+                    .cloned()
+                    .collect(),
+                (None, Some(start), Some(end)) => records
+                    .iter()
+                    .filter(|r| r.1 >= start && r.1 <= end)
+                    .take(limit)
+                    .cloned()
+                    .collect(),
+                _ => Err(format_err!("Invalid query {:?}", q))?,
+            };
+            Ok(Box::new(ok(page)) as Page<Record>)
+        };
+        let history = History::new(None, Some(1000), HistoryDirection::Backward, 50, fetch);
+        let records = history.collect().wait().unwrap();
+        assert_eq!(records.len(), 1110);
+        assert_eq!(records.first(), Some(&Record(1109, 100)));
+        assert_eq!(records.last(), Some(&Record(0, 10)));
+        assert!(records.windows(2).all(|w| w[0].0 == w[1].0 + 1));
+    }
+
+    #[test]
+    fn windows() {
+        let (history, queries) = history(100, 1000, Some(0), None, HistoryDirection::Forward, 100);
+        let records = history.collect().wait().unwrap();
+        assert_eq!(records.len(), 100);
+        assert!(records.windows(2).all(|w| w[0].0 + 1 == w[1].0));
+
+        let queries = queries.lock().unwrap();
+        assert_eq!(queries.len(), 12);
+        assert_eq!(queries[10].start_time, Some(1000));
+        assert_eq!(queries[10].end_time, Some(1099));
+        assert_eq!(queries[11].from_id, Some(10));
+    }
+
+    static DAY: u64 = 86_400_000;
+
+    // A burst of 1500 records, hourly ones and a few more after a 3 days gap,
+    // served like allOrders: time ranges are at most a day long, ascending from their start
+    fn daily_history(
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        direction: HistoryDirection,
+    ) -> History<Record> {
+        let records: Vec<_> = (0..1500)
+            .map(|i| Record(i, 2 * DAY + i as u64))
+            .chain((1500..1510).map(|i| Record(i, 5 * DAY + (i as u64 - 1500) * 3_600_000)))
+            .chain((1510..1520).map(|i| Record(i, 9 * DAY + i as u64)))
+            .collect();
+        History::new(start_time, end_time, direction, DAY, move |q| {
+            let limit = q.limit.unwrap() as usize;
+            let (start, end) = match (q.from_id, q.start_time, q.end_time) {
+                (Some(id), None, None) => {
+                    let page = records.iter().filter(|r| r.0 >= id).take(limit);
+                    return Ok(Box::new(ok(page.cloned().collect())));
+                }
+                (None, Some(start), Some(end)) if end >= start && end - start <= DAY => {
+                    (start, end)
+                }
+                (None, Some(start), None) => (start, start + DAY),
+                (None, None, Some(end)) => (end.saturating_sub(DAY), end),
+                _ => Err(format_err!("Invalid query {:?}", q))?,
+            };
+            let page = records
+                .iter()
+                .filter(|r| r.1 >= start && r.1 <= end)
+                .take(limit);
+            Ok(Box::new(ok(page.cloned().collect())))
+        })
+    }
+
+    #[test]
+    fn daily_windows() {
+        let records = daily_history(Some(DAY), Some(10 * DAY), HistoryDirection::Forward)
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(records.len(), 1520);
+        assert!(records.windows(2).all(|w| w[0].0 + 1 == w[1].0));
+
+        for &start_time in &[Some(DAY), None] {
+            let records = daily_history(start_time, Some(10 * DAY), HistoryDirection::Backward)
+                .collect()
+                .wait()
+                .unwrap();
+            assert_eq!(records.len(), 1520);
+            assert!(records.windows(2).all(|w| w[0].0 == w[1].0 + 1));
+        }
+
+        let records = daily_history(
+            Some(5 * DAY + 3 * 3_600_000),
+            Some(9 * DAY + 1515),
+            HistoryDirection::Backward,
+        )
+        .collect()
+        .wait()
+        .unwrap();
+        assert_eq!(records.first(), Some(&Record(1515, 9 * DAY + 1515)));
+        assert_eq!(records.last(), Some(&Record(1503, 5 * DAY + 3 * 3_600_000)));
+        assert_eq!(records.len(), 13);
+    }
+
+    // Minute klines from 0 to n minutes, except the `missing` ones
+    fn kline_history(n: u64, missing: &'static [u64], start_time: u64) -> KlineHistory {
+        let interval = KlineInterval::Minute1;
+        let klines: Vec<_> = (0..n)
+            .filter(|i| !missing.contains(i))
+            .map(|i| KlineMsg {
+                open_time: i * 60_000,
+                open: Decimal::new(1, 0),
+                high: Decimal::new(1, 0),
+                low: Decimal::new(1, 0),
+                close: Decimal::new(1, 0),
+                volume: Decimal::new(1, 0),
+                close_time: i * 60_000 + 59_999,
+                quote_asset_volume: Decimal::new(1, 0),
+                number_of_trades: 1,
+                taker_buy_base_asset_volume: Decimal::new(1, 0),
+                taker_buy_quote_asset_volume: Decimal::new(1, 0),
+            })
+            .collect();
+        KlineHistory::new(interval, start_time, None, move |start| {
+            let page = klines
+                .iter()
+                .filter(|k| k.open_time >= start)
+                .take(PAGE_LIMIT as usize)
+                .cloned()
+                .collect();
+            Ok(Box::new(ok(page)))
+        })
+    }
+
+    #[test]
+    fn kline_gaps() {
+        let mut history = kline_history(2500, &[1200, 1201, 1202], 0);
+        let klines = history.by_ref().collect().wait().unwrap();
+        assert_eq!(klines.len(), 2497);
+        assert_eq!(
+            history.gaps(),
+            &[KlineGap {
+                from: 1200 * 60_000,
+                to: 1203 * 60_000
+            }]
+        );
+
+        let mut history = kline_history(2500, &[1000], 0).resume_from(999 * 60_000);
+        let klines = history.by_ref().collect().wait().unwrap();
+        assert_eq!(klines.first().map(|k| k.open_time), Some(1001 * 60_000));
+        assert_eq!(klines.len(), 1499);
+        assert_eq!(history.gaps().len(), 1);
+
+        // Klines missing at the start of the range, which is not aligned on a minute
+        let mut history = kline_history(2500, &[0, 1, 2], 0);
+        assert_eq!(history.by_ref().collect().wait().unwrap().len(), 2497);
+        assert_eq!(
+            history.gaps(),
+            &[KlineGap {
+                from: 0,
+                to: 3 * 60_000
+            }]
+        );
+        let mut history = kline_history(2500, &[1, 2], 30_000);
+        assert_eq!(history.by_ref().collect().wait().unwrap().len(), 2497);
+        assert_eq!(
+            history.gaps(),
+            &[KlineGap {
+                from: 60_000,
+                to: 3 * 60_000
+            }]
+        );
+
+        let mut history = kline_history(2500, &[], 0);
+        history.end_time = Some(2499 * 60_000);
+        assert_eq!(history.by_ref().collect().wait().unwrap().len(), 2500);
+        assert!(history.gaps().is_empty());
+
+        let mut history = kline_history(2500, &[2497, 2498, 2499], 0);
+        history.end_time = Some(2499 * 60_000 + 30_000);
+        let klines = history.by_ref().collect().wait().unwrap();
+        assert_eq!(klines.len(), 2497);
+        assert_eq!(
+            history.gaps(),
+            &[KlineGap {
+                from: 2497 * 60_000,
+                to: 2500 * 60_000
+            }]
+        );
+    }
+}
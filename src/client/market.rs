@@ -1,15 +1,18 @@
-use std::collections::HashMap;
-use std::iter::FromIterator;
-
 use failure::Error;
 use futures::Future;
 use serde_json::json;
-use serde_json::Value;
 
-use super::Binance;
-use decimal::Decimal;
+use super::{Binance, HistoryQuery};
+use error::BinanceError;
 use error::Result;
-use model::{KlineMsg, OrderBookMsg, PriceMsg, PriceStatsMsg, TickerMsg};
+use model::websocket::{AggregateTrade, Trade};
+use model::{
+    AvgPriceMsg, KlineInterval, KlineMsg, OrderBookMsg, PriceMsg, PriceStatsMsg,
+    RollingPriceStatsMsg, TickerMsg, WindowSize,
+};
+
+// Symbols of a single rolling window ticker request
+static ROLLING_TICKER_MAX_SYMBOLS: usize = 100;
 
 // Market Data endpoints
 impl Binance {
@@ -25,7 +28,75 @@ impl Binance {
         let limit = limit.into().unwrap_or(100);
         let params = json! {{"symbol": symbol, "limit": limit}};
 
-        Ok(self.transport.get("/api/v1/depth", Some(params))?)
+        Ok(self.transport.get("/api/v3/depth", Some(params))?)
+    }
+
+    // Recent trades (Default 500; max 1000)
+    pub fn get_trades<I>(
+        &self,
+        symbol: &str,
+        limit: I,
+    ) -> Result<impl Future<Item = Vec<Trade>, Error = Error>>
+    where
+        I: Into<Option<u64>>,
+    {
+        let params = json! {{"symbol": symbol, "limit": limit.into()}};
+        let symbol = symbol.to_string();
+        Ok(self
+            .transport
+            .get("/api/v3/trades", Some(params))?
+            .map(move |trades| with_symbol(&symbol, trades)))
+    }
+
+    // Older trades from `from_id`, or the most recent ones (Default 500; max 1000).
+    // Requires an API key.
+    pub fn get_historical_trades<F, I>(
+        &self,
+        symbol: &str,
+        from_id: F,
+        limit: I,
+    ) -> Result<impl Future<Item = Vec<Trade>, Error = Error>>
+    where
+        F: Into<Option<i64>>,
+        I: Into<Option<u64>>,
+    {
+        let params = json! {{"symbol": symbol, "fromId": from_id.into(), "limit": limit.into()}};
+        let symbol = symbol.to_string();
+        Ok(self
+            .transport
+            .get("/api/v3/historicalTrades", Some(params))?
+            .map(move |trades| with_symbol(&symbol, trades)))
+    }
+
+    // Aggregate trades from `from_id`, or between `start_time` and `end_time` less than an hour apart
+    // (Default 500; max 1000). See `agg_trades_history` to download a longer range.
+    pub fn get_agg_trades(
+        &self,
+        symbol: &str,
+        query: HistoryQuery,
+    ) -> Result<impl Future<Item = Vec<AggregateTrade>, Error = Error>> {
+        let params = json! {{
+            "symbol": symbol,
+            "fromId": query.from_id,
+            "startTime": query.start_time,
+            "endTime": query.end_time,
+            "limit": query.limit,
+        }};
+        let symbol = symbol.to_string();
+        let trades = self.transport.get("/api/v3/aggTrades", Some(params))?.map(
+            move |trades: Vec<AggregateTrade>| {
+                trades
+                    .into_iter()
+                    .map(|trade| AggregateTrade {
+                        event_type: "aggTrade".to_string(),
+                        event_time: trade.trade_time,
+                        symbol: symbol.clone(),
+                        ..trade
+                    })
+                    .collect()
+            },
+        );
+        Ok(trades)
     }
 
     // Latest price for ONE symbol.
@@ -39,6 +110,26 @@ impl Binance {
         Ok(self.transport.get::<_, ()>("/api/v3/ticker/price", None)?)
     }
 
+    // Latest price for a list of symbols.
+    pub fn get_price_multi(
+        &self,
+        symbols: &[&str],
+    ) -> Result<impl Future<Item = Vec<PriceMsg>, Error = Error>> {
+        let params = json! {{"symbols": symbols}};
+        let prices = self.transport.get("/api/v3/ticker/price", Some(params))?;
+        Ok(prices)
+    }
+
+    // Current average price for ONE symbol, over the `avg_price_mins` of the PERCENT_PRICE filter
+    pub fn get_average_price(
+        &self,
+        symbol: &str,
+    ) -> Result<impl Future<Item = AvgPriceMsg, Error = Error>> {
+        let params = json! {{"symbol": symbol}};
+        let avg_price = self.transport.get("/api/v3/avgPrice", Some(params))?;
+        Ok(avg_price)
+    }
+
     // -> Best price/qty on the order book for ONE symbol
     pub fn get_book_ticker(
         &self,
@@ -58,28 +149,84 @@ impl Binance {
             .get::<_, ()>("/api/v3/ticker/bookTicker", None)?)
     }
 
+    // -> Best price/qty on the order book for a list of symbols.
+    pub fn get_book_ticker_multi(
+        &self,
+        symbols: &[&str],
+    ) -> Result<impl Future<Item = Vec<TickerMsg>, Error = Error>> {
+        let params = json! {{"symbols": symbols}};
+        let tickers = self
+            .transport
+            .get("/api/v3/ticker/bookTicker", Some(params))?;
+        Ok(tickers)
+    }
+
     // 24hr ticker price change statistics
     pub fn get_24h_price_stats(
         &self,
         symbol: &str,
     ) -> Result<impl Future<Item = PriceStatsMsg, Error = Error>> {
         let params = json! {{"symbol": symbol}};
-        Ok(self.transport.get("/api/v1/ticker/24hr", Some(params))?)
+        Ok(self.transport.get("/api/v3/ticker/24hr", Some(params))?)
     }
 
     // 24hr ticker price change statistics
     pub fn get_24h_price_stats_all(
         &self,
     ) -> Result<impl Future<Item = Vec<PriceStatsMsg>, Error = Error>> {
-        Ok(self.transport.get::<_, ()>("/api/v1/ticker/24hr", None)?)
+        Ok(self.transport.get::<_, ()>("/api/v3/ticker/24hr", None)?)
     }
 
-    // Returns up to 'limit' klines for given symbol and interval ("1m", "5m", ...)
+    // 24hr ticker price change statistics for a list of symbols
+    pub fn get_24h_price_stats_multi(
+        &self,
+        symbols: &[&str],
+    ) -> Result<impl Future<Item = Vec<PriceStatsMsg>, Error = Error>> {
+        let params = json! {{"symbols": symbols}};
+        let stats = self.transport.get("/api/v3/ticker/24hr", Some(params))?;
+        Ok(stats)
+    }
+
+    // Price change statistics over a rolling window (Default 1d)
+    pub fn get_rolling_price_stats<W>(
+        &self,
+        symbol: &str,
+        window_size: W,
+    ) -> Result<impl Future<Item = RollingPriceStatsMsg, Error = Error>>
+    where
+        W: Into<Option<WindowSize>>,
+    {
+        let params = json! {{"symbol": symbol, "windowSize": window_param(window_size)?}};
+        let stats = self.transport.get("/api/v3/ticker", Some(params))?;
+        Ok(stats)
+    }
+
+    // Price change statistics over a rolling window for a list of symbols (at most 100)
+    pub fn get_rolling_price_stats_multi<W>(
+        &self,
+        symbols: &[&str],
+        window_size: W,
+    ) -> Result<impl Future<Item = Vec<RollingPriceStatsMsg>, Error = Error>>
+    where
+        W: Into<Option<WindowSize>>,
+    {
+        if symbols.len() > ROLLING_TICKER_MAX_SYMBOLS {
+            Err(BinanceError::TooManySymbols(
+                symbols.len(),
+                ROLLING_TICKER_MAX_SYMBOLS,
+            ))?
+        }
+        let params = json! {{"symbols": symbols, "windowSize": window_param(window_size)?}};
+        let stats = self.transport.get("/api/v3/ticker", Some(params))?;
+        Ok(stats)
+    }
+
+    // Returns up to 'limit' klines for given symbol and interval
     // https://github.com/binance-exchange/binance-official-api-docs/blob/master/rest-api.md#klinecandlestick-data
     pub fn get_klines<S3, S4, S5>(
         &self,
         symbol: &str,
-        interval: &str,
+        interval: KlineInterval,
         limit: S3,
         start_time: S4,
         end_time: S5,
@@ -89,54 +236,69 @@ impl Binance {
         S4: Into<Option<u64>>,
         S5: Into<Option<u64>>,
     {
-        let mut params = vec![
-            ("symbol", symbol.to_string()),
-            ("interval", interval.to_string()),
-        ];
-
-        // Add three optional parameters
-        if let Some(lt) = limit.into() {
-            params.push(("limit", lt.to_string()));
-        }
-        if let Some(st) = start_time.into() {
-            params.push(("startTime", st.to_string()));
-        }
-        if let Some(et) = end_time.into() {
-            params.push(("endTime", et.to_string()));
-        }
-        let params: HashMap<&str, String> = HashMap::from_iter(params);
-
-        // todo: check if kline parsing could be done with serde
-        // Ok(self.transport.get("/api/v1/klines", Some(params))?)
-
-        let klines =
-            self.transport
-                .get("/api/v1/klines", Some(params))?
-                .map(|data: Vec<Vec<Value>>| {
-                    data.iter()
-                        .map(|row| KlineMsg {
-                            open_time: to_u64(&row[0]),
-                            open: to_decimal(&row[1]),
-                            high: to_decimal(&row[2]),
-                            low: to_decimal(&row[3]),
-                            close: to_decimal(&row[4]),
-                            volume: to_decimal(&row[5]),
-                            close_time: to_u64(&row[6]),
-                            quote_asset_volume: to_decimal(&row[7]),
-                            number_of_trades: to_u64(&row[8]),
-                            taker_buy_base_asset_volume: to_decimal(&row[9]),
-                            taker_buy_quote_asset_volume: to_decimal(&row[10]),
-                        })
-                        .collect()
-                });
+        let params = json! {{
+            "symbol": symbol,
+            "interval": interval,
+            "limit": limit.into(),
+            "startTime": start_time.into(),
+            "endTime": end_time.into(),
+        }};
+        let klines = self.transport.get("/api/v3/klines", Some(params))?;
         Ok(klines)
     }
 }
 
-fn to_u64(v: &Value) -> u64 {
-    v.as_u64().unwrap()
+fn window_param<W: Into<Option<WindowSize>>>(window_size: W) -> Result<Option<String>> {
+    match window_size.into() {
+        Some(window_size) if !window_size.is_valid() => {
+            Err(BinanceError::InvalidWindowSize(window_size.to_string()))?
+        }
+        window_size => Ok(window_size.map(|w| w.to_string())),
+    }
+}
+
+// Fills in the stream fields missing from the REST trades
+fn with_symbol(symbol: &str, trades: Vec<Trade>) -> Vec<Trade> {
+    trades
+        .into_iter()
+        .map(|trade| Trade {
+            event_type: "trade".to_string(),
+            event_time: trade.trade_time,
+            symbol: symbol.to_string(),
+            ..trade
+        })
+        .collect()
 }
 
-fn to_decimal(v: &Value) -> Decimal {
-    v.as_str().unwrap().parse().unwrap()
+#[cfg(test)]
+mod test {
+    use client::Binance;
+    use error::BinanceError;
+    use model::WindowSize;
+
+    #[test]
+    fn rolling_price_stats_params() {
+        let binance = Binance::new();
+        let symbols = vec!["BNBBTC"; 100];
+        assert!(binance
+            .get_rolling_price_stats_multi(&symbols, WindowSize::Hours(4))
+            .is_ok());
+
+        let symbols = vec!["BNBBTC"; 101];
+        match binance
+            .get_rolling_price_stats_multi(&symbols, None)
+            .map_err(|e| e.downcast::<BinanceError>())
+        {
+            Err(Ok(BinanceError::TooManySymbols(101, 100))) => {}
+            _ => panic!("101 symbols should be rejected"),
+        }
+
+        match binance
+            .get_rolling_price_stats("BNBBTC", WindowSize::Hours(24))
+            .map_err(|e| e.downcast::<BinanceError>())
+        {
+            Err(Ok(BinanceError::InvalidWindowSize(window))) => assert_eq!(window, "24h"),
+            _ => panic!("24h should be rejected"),
+        }
+    }
 }
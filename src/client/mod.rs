@@ -1,26 +1,74 @@
 mod account;
 mod general;
+mod history;
 mod market;
 mod userstream;
 mod websocket;
 
+pub use self::account::SymbolCancel;
+pub use self::history::{
+    History, HistoryDirection, HistoryItem, HistoryQuery, KlineGap, KlineHistory,
+};
+pub use self::userstream::UserDataStream;
+pub use self::websocket::{BinanceWebsocket, WebsocketHandle};
+
+use config::Config;
+use filter::OrderValidator;
+use model::order::NewOrder;
 use transport::Transport;
 
 #[derive(Clone)]
 pub struct Binance {
     pub transport: Transport,
+    order_validator: Option<OrderValidator>,
 }
 
 impl Binance {
     pub fn new() -> Self {
         Binance {
             transport: Transport::new(),
+            order_validator: None,
         }
     }
 
     pub fn with_credential(api_key: &str, api_secret: &str) -> Self {
         Binance {
             transport: Transport::with_credential(api_key, api_secret),
+            order_validator: None,
+        }
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Binance {
+            transport: Transport::with_config(None, config),
+            order_validator: None,
+        }
+    }
+
+    pub fn with_credential_and_config(api_key: &str, api_secret: &str, config: Config) -> Self {
+        Binance {
+            transport: Transport::with_config(Some((api_key, api_secret)), config),
+            order_validator: None,
+        }
+    }
+
+    // Checks every order against the symbol filters before sending it.
+    // The prices and quantities given to the order helpers (`limit_buy`, ...) are also
+    // rounded to the symbol grid, see `OrderValidator::normalize`.
+    pub fn with_order_validator(mut self, validator: OrderValidator) -> Self {
+        self.order_validator = Some(validator);
+        self
+    }
+
+    pub fn order_validator(&self) -> Option<&OrderValidator> {
+        self.order_validator.as_ref()
+    }
+
+    // Rounds an order to its symbol grid if an order validator is set
+    pub fn normalize_order(&self, order: NewOrder) -> NewOrder {
+        match self.order_validator {
+            Some(ref validator) => validator.normalize(order),
+            None => order,
         }
     }
 }
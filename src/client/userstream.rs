@@ -1,11 +1,22 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
 use failure::Error;
-use futures::Future;
+use futures::{try_ready, Async, Future, Poll, Stream};
+use serde_json::Value;
+use tokio::executor::{DefaultExecutor, Executor};
+use tokio::timer::{Delay, Interval};
 
 use client::Binance;
+use config::Backoff;
 use error::Result;
+use model::websocket::{BinanceSubscription, BinanceWebsocketMessage};
 use model::UserDataStreamMsg;
 
-static USER_DATA_STREAM: &'static str = "/api/v1/userDataStream";
+static USER_DATA_STREAM: &'static str = "/api/v3/userDataStream";
+
+// Listen keys expire after 60 minutes without a keep-alive
+static KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
 
 impl Binance {
     // User Stream
@@ -21,10 +32,13 @@ impl Binance {
         &self,
         listen_key: &str,
     ) -> Result<impl Future<Item = (), Error = Error>> {
-        let success = self.transport.put(
-            USER_DATA_STREAM,
-            Some(vec![("listen_key", listen_key.to_string())]),
-        )?;
+        let success = self
+            .transport
+            .put(
+                USER_DATA_STREAM,
+                Some(vec![("listenKey", listen_key.to_string())]),
+            )?
+            .map(|_: Value| ());
         Ok(success)
     }
 
@@ -32,10 +46,375 @@ impl Binance {
         &self,
         listen_key: &str,
     ) -> Result<impl Future<Item = (), Error = Error>> {
-        let success = self.transport.delete(
-            USER_DATA_STREAM,
-            Some(vec![("listen_key", listen_key.to_string())]),
-        )?;
+        let success = self
+            .transport
+            .delete(
+                USER_DATA_STREAM,
+                Some(vec![("listenKey", listen_key.to_string())]),
+            )?
+            .map(|_: Value| ());
         Ok(success)
     }
+
+    // User data stream managing its listen key: kept alive every 30 minutes,
+    // recreated along with the connection when it expires or the connection drops.
+    pub fn user_data_stream(&self) -> UserDataStream {
+        UserDataStream::with_api(self.clone())
+    }
+}
+
+type Request<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
+type Connection = Box<dyn Stream<Item = BinanceWebsocketMessage, Error = Error> + Send>;
+
+// Requests made by `UserDataStream`, stubbed in tests
+trait UserDataApi: Send {
+    fn start(&self) -> Result<Request<UserDataStreamMsg>>;
+    fn keep_alive(&self, listen_key: &str) -> Result<Request<()>>;
+    fn close(&self, listen_key: &str) -> Result<Request<()>>;
+    fn connect(&self, listen_key: &str) -> Request<Connection>;
+}
+
+impl UserDataApi for Binance {
+    fn start(&self) -> Result<Request<UserDataStreamMsg>> {
+        Ok(Box::new(self.user_stream_start()?))
+    }
+
+    fn keep_alive(&self, listen_key: &str) -> Result<Request<()>> {
+        Ok(Box::new(self.user_stream_keep_alive(listen_key)?))
+    }
+
+    fn close(&self, listen_key: &str) -> Result<Request<()>> {
+        Ok(Box::new(self.user_stream_close(listen_key)?))
+    }
+
+    fn connect(&self, listen_key: &str) -> Request<Connection> {
+        let subscription = BinanceSubscription::UserData(listen_key.to_string());
+        Box::new(
+            self.websocket()
+                .subscribe(subscription)
+                .map(|ws| Box::new(ws) as Connection),
+        )
+    }
+}
+
+enum UserDataState {
+    // Waiting before (re)starting, with the number of failed attempts
+    Waiting(Delay, u32),
+    Starting(Request<UserDataStreamMsg>, u32),
+    Connecting(Request<Connection>, u32),
+    Streaming(Connection, Interval),
+}
+
+pub struct UserDataStream {
+    api: Box<dyn UserDataApi>,
+    backoff: Backoff,
+    keep_alive_interval: Duration,
+    listen_key: Option<String>,
+    state: UserDataState,
+    keep_alive: Option<Request<()>>,
+    // control events waiting to be yielded
+    events: VecDeque<BinanceWebsocketMessage>,
+}
+
+impl UserDataStream {
+    fn with_api<A: UserDataApi + 'static>(api: A) -> Self {
+        UserDataStream {
+            api: Box::new(api),
+            backoff: Backoff::default(),
+            keep_alive_interval: KEEP_ALIVE_INTERVAL,
+            listen_key: None,
+            state: UserDataState::Waiting(Delay::new(Instant::now()), 0),
+            keep_alive: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    // Delay between failed attempts to obtain a listen key or to connect
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.keep_alive_interval = interval;
+        self
+    }
+
+    pub fn listen_key(&self) -> Option<&str> {
+        self.listen_key.as_deref()
+    }
+
+    // Drops the current listen key and connection and starts over with a new key
+    fn restart(&mut self) {
+        if let Some(listen_key) = self.listen_key.take() {
+            self.close(&listen_key);
+            self.events
+                .push_back(BinanceWebsocketMessage::Disconnected(vec![
+                    BinanceSubscription::UserData(listen_key),
+                ]));
+        }
+        self.keep_alive = None;
+        self.state = UserDataState::Waiting(Delay::new(Instant::now()), 0);
+    }
+
+    fn retry(&mut self, attempt: u32, error: &Error) {
+        if let Some(listen_key) = self.listen_key.take() {
+            self.close(&listen_key);
+        }
+        let delay = self.backoff.delay(attempt);
+        warn!(
+            "[UserDataStream] Failed to start: {}, retrying in {:?}",
+            error, delay
+        );
+        self.state = UserDataState::Waiting(Delay::new(Instant::now() + delay), attempt + 1);
+    }
+
+    // Closes the listen key in the background, if an executor is available
+    fn close(&self, listen_key: &str) {
+        match self.api.close(listen_key) {
+            Ok(close) => {
+                let close =
+                    close.map_err(|e| warn!("[UserDataStream] Failed to close listen key: {}", e));
+                if DefaultExecutor::current().spawn(Box::new(close)).is_err() {
+                    warn!("[UserDataStream] No executor to close listen key");
+                }
+            }
+            Err(e) => warn!("[UserDataStream] Failed to close listen key: {}", e),
+        }
+    }
+
+    fn poll_keep_alive(&mut self) -> Result<()> {
+        let listen_key = match self.listen_key {
+            Some(ref listen_key) => listen_key.clone(),
+            None => return Ok(()),
+        };
+
+        if let UserDataState::Streaming(_, ref mut interval) = self.state {
+            while let Async::Ready(Some(_)) = interval.poll()? {
+                trace!("[UserDataStream] Keep alive {}", listen_key);
+                self.keep_alive = Some(self.api.keep_alive(&listen_key)?);
+            }
+        }
+
+        let result = match self.keep_alive {
+            Some(ref mut keep_alive) => keep_alive.poll(),
+            None => return Ok(()),
+        };
+        match result {
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(())) => self.keep_alive = None,
+            Err(e) => {
+                warn!("[UserDataStream] Keep alive failed: {}", e);
+                self.restart();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Stream for UserDataStream {
+    type Item = BinanceWebsocketMessage;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            self.poll_keep_alive()?;
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+
+            let state = match self.state {
+                UserDataState::Waiting(ref mut delay, attempt) => {
+                    try_ready!(delay.poll());
+                    UserDataState::Starting(self.api.start()?, attempt)
+                }
+                UserDataState::Starting(ref mut start, attempt) => match start.poll() {
+                    Ok(Async::Ready(UserDataStreamMsg { listen_key })) => {
+                        let connect = self.api.connect(&listen_key);
+                        self.listen_key = Some(listen_key);
+                        UserDataState::Connecting(connect, attempt)
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        self.retry(attempt, &e);
+                        continue;
+                    }
+                },
+                UserDataState::Connecting(ref mut connect, attempt) => match connect.poll() {
+                    Ok(Async::Ready(ws)) => {
+                        let listen_key = self.listen_key.clone().unwrap_or_default();
+                        self.events
+                            .push_back(BinanceWebsocketMessage::Reconnected(vec![
+                                BinanceSubscription::UserData(listen_key),
+                            ]));
+                        let period = self.keep_alive_interval;
+                        UserDataState::Streaming(ws, Interval::new(Instant::now() + period, period))
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        self.retry(attempt, &e);
+                        continue;
+                    }
+                },
+                UserDataState::Streaming(ref mut ws, _) => match ws.poll() {
+                    Ok(Async::Ready(Some(BinanceWebsocketMessage::ListenKeyExpired(expired)))) => {
+                        warn!("[UserDataStream] Listen key expired");
+                        self.restart();
+                        return Ok(Async::Ready(Some(
+                            BinanceWebsocketMessage::ListenKeyExpired(expired),
+                        )));
+                    }
+                    Ok(Async::Ready(Some(message))) => return Ok(Async::Ready(Some(message))),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(None)) => {
+                        warn!("[UserDataStream] Connection closed");
+                        self.restart();
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("[UserDataStream] Connection error: {}", e);
+                        self.restart();
+                        continue;
+                    }
+                },
+            };
+            self.state = state;
+        }
+    }
+}
+
+impl Drop for UserDataStream {
+    fn drop(&mut self) {
+        if let Some(listen_key) = self.listen_key.take() {
+            self.close(&listen_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use futures::future::{lazy, ok};
+    use futures::stream::{iter_ok, poll_fn};
+    use futures::{Async, Future, Stream};
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    use super::{Connection, Request, UserDataApi, UserDataStream};
+    use error::Result;
+    use model::websocket::{BinanceSubscription, BinanceWebsocketMessage, ListenKeyExpired};
+    use model::UserDataStreamMsg;
+
+    // Hands out key1, key2, ... and logs every request
+    #[derive(Clone, Default)]
+    struct Stub {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Stub {
+        fn log(&self, request: String) -> usize {
+            let mut log = self.log.lock().unwrap();
+            log.push(request);
+            log.iter().filter(|r| r.starts_with("start")).count()
+        }
+
+        fn requests(&self, prefix: &str) -> Vec<String> {
+            let log = self.log.lock().unwrap();
+            log.iter()
+                .filter(|r| r.starts_with(prefix))
+                .cloned()
+                .collect()
+        }
+    }
+
+    impl UserDataApi for Stub {
+        fn start(&self) -> Result<Request<UserDataStreamMsg>> {
+            let n = self.log("start".into());
+            let listen_key = format!("key{}", n);
+            Ok(Box::new(ok(UserDataStreamMsg { listen_key })))
+        }
+
+        fn keep_alive(&self, listen_key: &str) -> Result<Request<()>> {
+            self.log(format!("keep_alive {}", listen_key));
+            Ok(Box::new(ok(())))
+        }
+
+        fn close(&self, listen_key: &str) -> Result<Request<()>> {
+            self.log(format!("close {}", listen_key));
+            Ok(Box::new(ok(())))
+        }
+
+        // key1 stays open 50 ms then drops, key2 expires at once, others stay open
+        fn connect(&self, listen_key: &str) -> Request<Connection> {
+            let connection: Connection = match listen_key {
+                "key1" => Box::new(
+                    Delay::new(Instant::now() + Duration::from_millis(50))
+                        .from_err()
+                        .into_stream()
+                        .filter_map(|_| None),
+                ),
+                "key2" => Box::new(iter_ok(vec![BinanceWebsocketMessage::ListenKeyExpired(
+                    ListenKeyExpired {
+                        event_type: "listenKeyExpired".into(),
+                        event_time: 1,
+                    },
+                )])),
+                _ => Box::new(poll_fn(|| Ok(Async::NotReady))),
+            };
+            Box::new(ok(connection))
+        }
+    }
+
+    fn key(message: &BinanceWebsocketMessage) -> String {
+        match *message {
+            BinanceWebsocketMessage::Reconnected(ref subs) => match subs[..] {
+                [BinanceSubscription::UserData(ref key)] => format!("reconnected {}", key),
+                _ => panic!("unexpected subscriptions {:?}", subs),
+            },
+            BinanceWebsocketMessage::Disconnected(ref subs) => match subs[..] {
+                [BinanceSubscription::UserData(ref key)] => format!("disconnected {}", key),
+                _ => panic!("unexpected subscriptions {:?}", subs),
+            },
+            BinanceWebsocketMessage::ListenKeyExpired(..) => "expired".into(),
+            _ => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn listen_key_lifecycle() -> Result<()> {
+        let stub = Stub::default();
+        let api = stub.clone();
+        let mut rt = Runtime::new()?;
+
+        let events = rt.block_on(lazy(move || {
+            UserDataStream::with_api(api)
+                .keep_alive_interval(Duration::from_millis(20))
+                .take(6)
+                .collect()
+        }))?;
+        let events: Vec<_> = events.iter().map(key).collect();
+        assert_eq!(
+            events,
+            vec![
+                "reconnected key1",
+                "disconnected key1",
+                "reconnected key2",
+                "expired",
+                "disconnected key2",
+                "reconnected key3",
+            ]
+        );
+
+        // Kept alive while connected, closed when replaced and when the stream is dropped
+        let keep_alives = stub.requests("keep_alive");
+        assert!(!keep_alives.is_empty());
+        assert!(keep_alives.iter().all(|r| r == "keep_alive key1"));
+        assert_eq!(
+            stub.requests("close"),
+            vec!["close key1", "close key2", "close key3"]
+        );
+        Ok(())
+    }
 }
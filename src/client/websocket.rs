@@ -1,77 +1,534 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::slice;
+use std::time::Instant;
 
 use failure::Error;
-use futures::stream::{SplitStream, Stream};
-use futures::{Future, Poll};
-use serde_json::from_str;
+use futures::future::{join_all, ok, result};
+use futures::stream::Stream;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
+use futures::{Async, AsyncSink, Future, Poll, Sink};
+use serde_json::{from_str, from_value, json, Value};
 use tokio::net::TcpStream;
+use tokio::timer::Delay;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tungstenite::Message;
 use url::Url;
 
 use crate::client::Binance;
-use crate::error::{BinanceError, Result};
-use crate::model::websocket::{
-    AccountUpdate, BinanceSubscription, BinanceWebsocketMessage, OrderUpdate,
-};
+use crate::config::Backoff;
+use crate::error::{BinanceError, BinanceResponseError, Result};
+use crate::model::websocket::{BinanceSubscription, BinanceWebsocketMessage};
 
-const WS_URL: &'static str = "wss://stream.binance.com:9443/ws";
+// Binance accepts at most 1024 streams on a single connection
+const MAX_STREAMS_PER_CONNECTION: usize = 1024;
 
 impl Binance {
     pub fn websocket(&self) -> BinanceWebsocket {
-        BinanceWebsocket {
-            subscriptions: HashMap::new(),
-        }
+        let ws_endpoint = self.transport.config().ws_endpoint.clone();
+        BinanceWebsocket::with_connector(move |streams| Box::new(connect(&ws_endpoint, streams)))
     }
 }
 
 #[allow(dead_code)]
 type WSStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-pub struct BinanceWebsocket {
-    subscriptions: HashMap<BinanceSubscription, SplitStream<WSStream>>,
+type ConnectionSink = Box<dyn Sink<SinkItem = Message, SinkError = tungstenite::Error> + Send>;
+type ConnectionStream = Box<dyn Stream<Item = Message, Error = tungstenite::Error> + Send>;
+type Connect = Box<dyn Future<Item = Connection, Error = Error> + Send>;
+
+// Opens a combined stream connection to the given streams
+type Connector = Box<dyn Fn(HashSet<String>) -> Connect + Send>;
+
+// A combined stream connection: /stream?streams=<stream1>/<stream2>/...
+struct Connection {
+    streams: HashSet<String>,
+    sink: ConnectionSink,
+    stream: ConnectionStream,
+    // messages waiting for the sink
+    outbox: VecDeque<Message>,
+    // requests sent on this connection and not answered yet: id -> streams being subscribed
+    in_flight: HashMap<u64, Vec<String>>,
+}
+
+// A connection being opened for a live subscription request
+struct Connecting {
+    request: u64,
+    streams: HashSet<String>,
+    connect: Connect,
 }
 
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    Subscribe,
+    Unsubscribe,
+    ListSubscriptions,
+}
+
+// A request of a `WebsocketHandle`, or of `BinanceWebsocket::subscribe` when nobody waits for the reply
+struct Command {
+    method: Method,
+    subscriptions: Vec<BinanceSubscription>,
+    reply: Option<oneshot::Sender<Result<Value>>>,
+}
+
+// A dropped connection waiting to be reopened with the same streams
+struct Reconnecting {
+    streams: HashSet<String>,
+    attempt: u32,
+    state: ReconnectState,
+}
+
+enum ReconnectState {
+    Waiting(Delay),
+    Connecting(Connect),
+}
+
+// A request possibly sent on several connections, answered once all of them replied
+struct PendingRequest {
+    remaining: usize,
+    results: Vec<Value>,
+    error: Option<Error>,
+    reply: Option<oneshot::Sender<Result<Value>>>,
+}
+
+pub struct BinanceWebsocket {
+    connector: Connector,
+    // stream name -> subscription, used to route the combined stream messages
+    subscriptions: HashMap<String, BinanceSubscription>,
+    // subscriptions of `subscribe` waiting for the first poll, to be sent together
+    queued: Vec<BinanceSubscription>,
+    connections: Vec<Connection>,
+    connecting: Vec<Connecting>,
+    // connection polled first, rotated for fairness
+    cursor: usize,
+    next_id: u64,
+    pending: HashMap<u64, PendingRequest>,
+    commands: UnboundedReceiver<Command>,
+    commands_tx: UnboundedSender<Command>,
+    reconnect: Option<Backoff>,
+    reconnecting: Vec<Reconnecting>,
+    // control events waiting to be yielded
+    events: VecDeque<BinanceWebsocketMessage>,
+    // failure of a request of `subscribe`, failing the stream
+    failure: Option<Error>,
+}
 impl BinanceWebsocket {
+    fn with_connector<F>(connector: F) -> Self
+    where
+        F: Fn(HashSet<String>) -> Connect + Send + 'static,
+    {
+        let (commands_tx, commands) = unbounded();
+        BinanceWebsocket {
+            connector: Box::new(connector),
+            subscriptions: HashMap::new(),
+            queued: Vec::new(),
+            connections: Vec::new(),
+            connecting: Vec::new(),
+            cursor: 0,
+            next_id: 1,
+            pending: HashMap::new(),
+            commands,
+            commands_tx,
+            reconnect: None,
+            reconnecting: Vec::new(),
+            events: VecDeque::new(),
+            failure: None,
+        }
+    }
+
+    // Subscribes when the websocket is first polled, together with the subscriptions chained before:
+    // they share the open connections and as few new ones as possible. Failures are reported by the stream.
     pub fn subscribe(
         mut self,
         subscription: BinanceSubscription,
     ) -> impl Future<Item = Self, Error = Error> {
-        let sub = match subscription {
-            BinanceSubscription::AggregateTrade(ref symbol) => format!("{}@aggTrade", symbol),
-            BinanceSubscription::Trade(ref symbol) => format!("{}@trade", symbol),
-            BinanceSubscription::Candlestick(ref symbol, ref interval) => {
-                format!("{}@kline_{}", symbol, interval)
-            }
-            BinanceSubscription::MiniTicker(ref symbol) => format!("{}@miniTicker", symbol),
-            BinanceSubscription::MiniTickerAll => "!miniTicker@arr".to_string(),
-            BinanceSubscription::Ticker(ref symbol) => format!("{}@ticker", symbol),
-            BinanceSubscription::TickerAll => "!ticker@arr".to_string(),
-            BinanceSubscription::OrderBook(ref symbol, depth) => {
-                format!("{}@depth{}", symbol, depth)
-            }
-            BinanceSubscription::DiffDepth(ref symbol) => format!("{}@depth", symbol),
-            BinanceSubscription::UserData(ref listen_key) => listen_key.clone(),
-        };
+        self.queued.push(subscription);
+        ok(self)
+    }
+
+    // Subscribes to all the streams at once, opening as few connections as the per connection stream limit allows
+    pub fn subscribe_all(
+        mut self,
+        subscriptions: Vec<BinanceSubscription>,
+    ) -> impl Future<Item = Self, Error = Error> {
+        let streams = self.add_subscriptions(subscriptions);
 
-        trace!("[Websocket] Subscribing to '{:?}'", subscription);
+        let connects: Vec<_> = streams
+            .chunks(MAX_STREAMS_PER_CONNECTION)
+            .map(|chunk| (self.connector)(chunk.iter().cloned().collect()))
+            .collect();
 
-        let endpoint = Url::parse(&format!("{}/{}", WS_URL, sub)).unwrap();
-        connect_async(endpoint)
-            .map(|(stream, _)| stream)
-            .map(|s| s.split().1)
-            .map(|stream| {
-                self.subscriptions.insert(subscription, stream);
-                self
-            })
-            .from_err()
+        join_all(connects).map(move |connections| {
+            self.connections.extend(connections);
+            self
+        })
     }
 
     pub fn unsubscribe(
         &mut self,
         subscription: &BinanceSubscription,
-    ) -> Option<SplitStream<WSStream>> {
-        self.subscriptions.remove(subscription)
+    ) -> Option<BinanceSubscription> {
+        let name = stream_name(subscription);
+        if let Some(i) = self.queued.iter().position(|s| stream_name(s) == name) {
+            return Some(self.queued.remove(i));
+        }
+        let subscription = self.subscriptions.remove(&name)?;
+
+        for connection in &mut self.connections {
+            if connection.streams.remove(&name) && !connection.streams.is_empty() {
+                // Nobody waits for the answer, the id is never pending
+                let id = self.next_id;
+                self.next_id += 1;
+                connection.send(Method::Unsubscribe, slice::from_ref(&name), id);
+            }
+        }
+        self.close_empty_connections();
+        for reconnecting in &mut self.reconnecting {
+            reconnecting.streams.remove(&name);
+        }
+        self.reconnecting.retain(|r| !r.streams.is_empty());
+
+        Some(subscription)
+    }
+
+    // Reopens dropped connections, waiting `backoff` between attempts, instead of failing the stream.
+    // `Disconnected` and `Reconnected` messages mark the possible gaps in the data.
+    pub fn reconnecting(mut self, backoff: Backoff) -> Self {
+        self.reconnect = Some(backoff);
+        self
+    }
+
+    // A handle to change the subscriptions of this websocket while it is being consumed as a stream.
+    // Requests are only processed while the websocket is polled.
+    pub fn handle(&self) -> WebsocketHandle {
+        WebsocketHandle {
+            commands: self.commands_tx.clone(),
+        }
+    }
+
+    // Registers the subscriptions for routing, returns the names of the streams not subscribed yet
+    fn add_subscriptions(&mut self, subscriptions: Vec<BinanceSubscription>) -> Vec<String> {
+        let mut streams = vec![];
+        for subscription in subscriptions {
+            let name = stream_name(&subscription);
+            if self.subscriptions.contains_key(&name) {
+                continue;
+            }
+            trace!("[Websocket] Subscribing to '{:?}'", subscription);
+            self.subscriptions.insert(name.clone(), subscription);
+            streams.push(name);
+        }
+        streams
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut sent = 0;
+
+        match command.method {
+            Method::Subscribe => {
+                let mut streams = self.add_subscriptions(command.subscriptions);
+                for connection in &mut self.connections {
+                    let free = MAX_STREAMS_PER_CONNECTION.saturating_sub(connection.streams.len());
+                    if streams.is_empty() || free == 0 {
+                        continue;
+                    }
+                    let rest = streams.split_off(free.min(streams.len()));
+                    connection.streams.extend(streams.iter().cloned());
+                    connection.send(Method::Subscribe, &streams, id);
+                    streams = rest;
+                    sent += 1;
+                }
+                // No room left on the open connections
+                for chunk in streams.chunks(MAX_STREAMS_PER_CONNECTION) {
+                    let streams: HashSet<_> = chunk.iter().cloned().collect();
+                    let connect = (self.connector)(streams.clone());
+                    self.connecting.push(Connecting {
+                        request: id,
+                        streams,
+                        connect,
+                    });
+                    sent += 1;
+                }
+            }
+            Method::Unsubscribe => {
+                let streams: Vec<_> = command
+                    .subscriptions
+                    .iter()
+                    .map(stream_name)
+                    .filter(|name| self.subscriptions.remove(name).is_some())
+                    .collect();
+                for connection in &mut self.connections {
+                    let removed: Vec<_> = streams
+                        .iter()
+                        .filter(|name| connection.streams.remove(*name))
+                        .cloned()
+                        .collect();
+                    // Connections left without streams are closed below, no need to ask
+                    if !removed.is_empty() && !connection.streams.is_empty() {
+                        connection.send(Method::Unsubscribe, &removed, id);
+                        sent += 1;
+                    }
+                }
+                self.close_empty_connections();
+                for reconnecting in &mut self.reconnecting {
+                    for name in &streams {
+                        reconnecting.streams.remove(name);
+                    }
+                }
+                self.reconnecting.retain(|r| !r.streams.is_empty());
+            }
+            Method::ListSubscriptions => {
+                for connection in &mut self.connections {
+                    connection.send(Method::ListSubscriptions, &[], id);
+                    sent += 1;
+                }
+            }
+        }
+
+        if sent == 0 {
+            if let Some(reply) = command.reply {
+                let _ = reply.send(Ok(Value::Array(vec![])));
+            }
+        } else {
+            self.pending.insert(
+                id,
+                PendingRequest {
+                    remaining: sent,
+                    results: vec![],
+                    error: None,
+                    reply: command.reply,
+                },
+            );
+        }
+    }
+
+    // Records the answer of one connection to the request `id`
+    fn complete(&mut self, id: u64, outcome: Result<Value>) {
+        let done = match self.pending.get_mut(&id) {
+            Some(pending) => {
+                match outcome {
+                    Ok(Value::Array(values)) => pending.results.extend(values),
+                    Ok(_) => {}
+                    Err(e) => pending.error = Some(e),
+                }
+                pending.remaining -= 1;
+                pending.remaining == 0
+            }
+            None => false,
+        };
+
+        if done {
+            let pending = self.pending.remove(&id).unwrap();
+            let reply = match pending.error {
+                Some(e) => Err(e),
+                None => Ok(Value::Array(pending.results)),
+            };
+            match pending.reply {
+                Some(tx) => {
+                    let _ = tx.send(reply);
+                }
+                None => {
+                    if let Err(e) = reply {
+                        self.failure = Some(e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_commands(&mut self) {
+        if !self.queued.is_empty() {
+            let subscriptions = self.queued.split_off(0);
+            self.handle_command(Command {
+                method: Method::Subscribe,
+                subscriptions,
+                reply: None,
+            });
+        }
+        while let Ok(Async::Ready(Some(command))) = self.commands.poll() {
+            self.handle_command(command);
+        }
+    }
+
+    fn poll_connecting(&mut self) {
+        let mut i = 0;
+        while i < self.connecting.len() {
+            match self.connecting[i].connect.poll() {
+                Ok(Async::NotReady) => i += 1,
+                Ok(Async::Ready(connection)) => {
+                    let request = self.connecting.remove(i).request;
+                    self.connections.push(connection);
+                    self.complete(request, Ok(Value::Null));
+                }
+                Err(e) => {
+                    let connecting = self.connecting.remove(i);
+                    self.forget(&connecting.streams);
+                    self.complete(connecting.request, Err(e));
+                }
+            }
+        }
+    }
+
+    fn poll_reconnecting(&mut self) -> Result<()> {
+        let backoff = match self.reconnect {
+            Some(backoff) => backoff,
+            None => return Ok(()),
+        };
+
+        let mut i = 0;
+        while i < self.reconnecting.len() {
+            let next = match self.reconnecting[i].state {
+                ReconnectState::Waiting(ref mut delay) => match delay.poll()? {
+                    Async::Ready(()) => {
+                        let streams = self.reconnecting[i].streams.clone();
+                        ReconnectState::Connecting((self.connector)(streams))
+                    }
+                    Async::NotReady => {
+                        i += 1;
+                        continue;
+                    }
+                },
+                ReconnectState::Connecting(ref mut connect) => match connect.poll() {
+                    Ok(Async::Ready(connection)) => {
+                        let reconnecting = self.reconnecting.remove(i);
+                        trace!("[Websocket] Reconnected: {:?}", reconnecting.streams);
+                        let subscriptions = self.subscriptions_of(&connection.streams);
+                        self.connections.push(connection);
+                        self.events
+                            .push_back(BinanceWebsocketMessage::Reconnected(subscriptions));
+                        continue;
+                    }
+                    Ok(Async::NotReady) => {
+                        i += 1;
+                        continue;
+                    }
+                    Err(e) => {
+                        let reconnecting = &mut self.reconnecting[i];
+                        reconnecting.attempt += 1;
+                        let delay = backoff.delay(reconnecting.attempt);
+                        warn!(
+                            "[Websocket] Reconnection failed: {}, retrying in {:?}",
+                            e, delay
+                        );
+                        ReconnectState::Waiting(Delay::new(Instant::now() + delay))
+                    }
+                },
+            };
+            // Poll the new state right away to register interest
+            self.reconnecting[i].state = next;
+        }
+        Ok(())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.connections.is_empty() && self.connecting.is_empty() && self.reconnecting.is_empty()
+    }
+
+    fn subscriptions_of(&self, streams: &HashSet<String>) -> Vec<BinanceSubscription> {
+        streams
+            .iter()
+            .filter_map(|name| self.subscriptions.get(name).cloned())
+            .collect()
+    }
+
+    // Unregisters streams whose subscription failed
+    fn forget<'a, I: IntoIterator<Item = &'a String>>(&mut self, streams: I) {
+        for name in streams {
+            self.subscriptions.remove(name);
+        }
+    }
+
+    // Drops the connections left without streams, failing the requests they did not answer
+    fn close_empty_connections(&mut self) {
+        let mut i = 0;
+        while i < self.connections.len() {
+            if !self.connections[i].streams.is_empty() {
+                i += 1;
+                continue;
+            }
+            let connection = self.connections.remove(i);
+            for (id, _) in connection.in_flight {
+                self.complete(id, Err(BinanceError::WebsocketClosed.into()));
+            }
+        }
+    }
+
+    fn close_connection(&mut self, idx: usize) {
+        let mut connection = self.connections.remove(idx);
+        trace!("[Websocket] Connection closed: {:?}", connection.streams);
+        // Subscriptions not confirmed are not reopened
+        for (id, streams) in connection.in_flight.drain() {
+            for name in &streams {
+                connection.streams.remove(name);
+            }
+            self.forget(&streams);
+            self.complete(id, Err(BinanceError::WebsocketClosed.into()));
+        }
+
+        match self.reconnect {
+            Some(_) if connection.streams.is_empty() => {}
+            Some(backoff) => {
+                let subscriptions = self.subscriptions_of(&connection.streams);
+                self.events
+                    .push_back(BinanceWebsocketMessage::Disconnected(subscriptions));
+                self.reconnecting.push(Reconnecting {
+                    streams: connection.streams,
+                    attempt: 0,
+                    state: ReconnectState::Waiting(Delay::new(Instant::now() + backoff.delay(0))),
+                });
+            }
+            None => {
+                for name in &connection.streams {
+                    self.subscriptions.remove(name);
+                }
+            }
+        }
+    }
+}
+
+impl Connection {
+    fn new(streams: HashSet<String>, sink: ConnectionSink, stream: ConnectionStream) -> Self {
+        Connection {
+            streams,
+            sink,
+            stream,
+            outbox: VecDeque::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    fn send(&mut self, method: Method, streams: &[String], id: u64) {
+        let subscribing = match method {
+            Method::Subscribe => streams.to_vec(),
+            _ => vec![],
+        };
+        let method = match method {
+            Method::Subscribe => "SUBSCRIBE",
+            Method::Unsubscribe => "UNSUBSCRIBE",
+            Method::ListSubscriptions => "LIST_SUBSCRIPTIONS",
+        };
+        let request = if streams.is_empty() {
+            json!({"method": method, "id": id})
+        } else {
+            json!({"method": method, "params": streams, "id": id})
+        };
+        trace!("[Websocket] Sending {}", request);
+        self.outbox.push_back(Message::Text(request.to_string()));
+        self.in_flight.insert(id, subscribing);
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while let Some(msg) = self.outbox.pop_front() {
+            if let AsyncSink::NotReady(msg) = self.sink.start_send(msg)? {
+                self.outbox.push_front(msg);
+                break;
+            }
+        }
+        self.sink.poll_complete()?;
+        Ok(())
     }
 }
 
@@ -80,71 +537,596 @@ impl Stream for BinanceWebsocket {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        let streams: Vec<_> = self
-            .subscriptions
-            .iter_mut()
-            .map(|(sub, stream)| {
-                stream
-                    .from_err()
-                    .and_then(move |msg| parse_message(sub.clone(), msg))
-            })
-            .collect();
+        self.poll_commands();
+        self.poll_connecting();
+        self.poll_reconnecting()?;
+
+        if let Some(e) = self.failure.take() {
+            return Err(e);
+        }
+        if let Some(event) = self.events.pop_front() {
+            return Ok(Async::Ready(Some(event)));
+        }
+        if self.is_idle() {
+            Err(BinanceError::NoStreamSubscribed)?
+        }
 
-        let streams = streams.into_iter().fold(
-            None,
-            |acc: Option<Box<Stream<Item = BinanceWebsocketMessage, Error = Error>>>, elem| {
-                match acc {
-                    Some(stream) => Some(Box::new(stream.select(elem.from_err()))),
-                    None => Some(Box::new(elem.from_err())),
+        let reconnect = self.reconnect.is_some();
+        let mut closed = vec![];
+        for (idx, connection) in self.connections.iter_mut().enumerate() {
+            match connection.flush() {
+                Ok(()) => {}
+                Err(e) if reconnect => {
+                    warn!("[Websocket] Connection error: {}", e);
+                    closed.push(idx);
                 }
-            },
-        );
-        match streams {
-            Some(mut streams) => streams.poll(),
-            None => Err(BinanceError::NoStreamSubscribed)?,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let len = self.connections.len();
+        let mut message = None;
+        for i in 0..len {
+            let idx = (self.cursor + i) % len;
+            if closed.contains(&idx) {
+                continue;
+            }
+            loop {
+                let msg = match self.connections[idx].stream.poll() {
+                    Ok(msg) => msg,
+                    Err(e) if reconnect => {
+                        warn!("[Websocket] Connection error: {}", e);
+                        closed.push(idx);
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                match msg {
+                    Async::Ready(Some(msg)) => match parse_message(&self.subscriptions, msg)? {
+                        Incoming::Message(msg) => {
+                            message = Some(*msg);
+                            break;
+                        }
+                        Incoming::Response(response) => {
+                            let connection = &mut self.connections[idx];
+                            let subscribing = connection
+                                .in_flight
+                                .remove(&response.id)
+                                .unwrap_or_default();
+                            let outcome = match response.error {
+                                Some(e) => {
+                                    for name in &subscribing {
+                                        connection.streams.remove(name);
+                                    }
+                                    self.forget(&subscribing);
+                                    Err(e.into())
+                                }
+                                None => Ok(response.result),
+                            };
+                            self.complete(response.id, outcome);
+                        }
+                        Incoming::Ignored => {}
+                    },
+                    Async::Ready(None) => {
+                        closed.push(idx);
+                        break;
+                    }
+                    Async::NotReady => break,
+                }
+            }
+            if message.is_some() {
+                self.cursor = idx + 1;
+                break;
+            }
+        }
+
+        closed.sort_unstable_by(|a, b| b.cmp(a));
+        for idx in closed {
+            self.close_connection(idx);
+        }
+        self.close_empty_connections();
+        // Reconnections start waiting right away
+        self.poll_reconnecting()?;
+
+        if let Some(e) = self.failure.take() {
+            return Err(e);
+        }
+        match message.or_else(|| self.events.pop_front()) {
+            Some(message) => Ok(Async::Ready(Some(message))),
+            None if self.is_idle() => Ok(Async::Ready(None)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+// Changes the subscriptions of a `BinanceWebsocket` over its open connections
+#[derive(Clone)]
+pub struct WebsocketHandle {
+    commands: UnboundedSender<Command>,
+}
+
+impl WebsocketHandle {
+    // Subscribes to the streams on the open connections, opening new ones when they are full
+    pub fn subscribe(
+        &self,
+        subscriptions: Vec<BinanceSubscription>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.request(Method::Subscribe, subscriptions).map(|_| ())
+    }
+
+    pub fn unsubscribe(
+        &self,
+        subscriptions: Vec<BinanceSubscription>,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.request(Method::Unsubscribe, subscriptions).map(|_| ())
+    }
+
+    // Names of the streams subscribed, as reported by the server
+    pub fn list_subscriptions(&self) -> impl Future<Item = Vec<String>, Error = Error> {
+        self.request(Method::ListSubscriptions, vec![])
+            .and_then(|streams| Ok(from_value(streams)?))
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        subscriptions: Vec<BinanceSubscription>,
+    ) -> impl Future<Item = Value, Error = Error> {
+        let (reply, response) = oneshot::channel();
+        let command = Command {
+            method,
+            subscriptions,
+            reply: Some(reply),
+        };
+        let sent = self
+            .commands
+            .unbounded_send(command)
+            .map_err(|_| BinanceError::WebsocketClosed.into());
+
+        result(sent).and_then(|_| {
+            response.then(|response| match response {
+                Ok(response) => response,
+                Err(_) => Err(BinanceError::WebsocketClosed)?,
+            })
+        })
+    }
+}
+
+fn connect(
+    ws_endpoint: &str,
+    streams: HashSet<String>,
+) -> impl Future<Item = Connection, Error = Error> {
+    let names: Vec<_> = streams.iter().map(|s| s.as_str()).collect();
+    let endpoint = format!("{}/stream?streams={}", ws_endpoint, names.join("/"));
+
+    result(Url::parse(&endpoint))
+        .from_err()
+        .and_then(|endpoint| connect_async(endpoint).from_err())
+        .map(|(stream, _)| {
+            let (sink, stream) = stream.split();
+            Connection::new(streams, Box::new(sink), Box::new(stream))
+        })
+}
+
+// Stream names are lowercase, as echoed back in the `stream` field of combined streams
+fn stream_name(subscription: &BinanceSubscription) -> String {
+    let lower = |symbol: &str| symbol.to_lowercase();
+    match *subscription {
+        BinanceSubscription::AggregateTrade(ref symbol) => format!("{}@aggTrade", lower(symbol)),
+        BinanceSubscription::Trade(ref symbol) => format!("{}@trade", lower(symbol)),
+        BinanceSubscription::Candlestick(ref symbol, ref interval) => {
+            format!("{}@kline_{}", lower(symbol), interval)
+        }
+        BinanceSubscription::MiniTicker(ref symbol) => format!("{}@miniTicker", lower(symbol)),
+        BinanceSubscription::MiniTickerAll => "!miniTicker@arr".to_string(),
+        BinanceSubscription::Ticker(ref symbol) => format!("{}@ticker", lower(symbol)),
+        BinanceSubscription::TickerAll => "!ticker@arr".to_string(),
+        BinanceSubscription::OrderBook(ref symbol, depth) => {
+            format!("{}@depth{}", lower(symbol), depth)
         }
+        BinanceSubscription::DiffDepth(ref symbol) => format!("{}@depth", lower(symbol)),
+        BinanceSubscription::UserData(ref listen_key) => listen_key.clone(),
     }
 }
 
-fn parse_message(sub: BinanceSubscription, msg: Message) -> Result<BinanceWebsocketMessage> {
+// Message of a combined stream
+#[derive(Debug, Clone, Deserialize)]
+struct StreamMessage {
+    stream: String,
+    data: Value,
+}
+
+// Answer to a SUBSCRIBE, UNSUBSCRIBE or LIST_SUBSCRIPTIONS request
+#[derive(Debug, Clone, Deserialize)]
+struct ResponseMessage {
+    id: u64,
+    #[serde(default)]
+    result: Value,
+    error: Option<BinanceResponseError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum IncomingText {
+    Stream(StreamMessage),
+    Response(ResponseMessage),
+}
+
+enum Incoming {
+    Message(Box<BinanceWebsocketMessage>),
+    Response(ResponseMessage),
+    // Message of a stream no longer subscribed
+    Ignored,
+}
+
+fn parse_message(
+    subscriptions: &HashMap<String, BinanceSubscription>,
+    msg: Message,
+) -> Result<Incoming> {
     let msg = match msg {
         Message::Text(msg) => msg,
-        Message::Binary(b) => return Ok(BinanceWebsocketMessage::Binary(b)),
-        Message::Pong(..) => return Ok(BinanceWebsocketMessage::Pong),
-        Message::Ping(..) => return Ok(BinanceWebsocketMessage::Ping),
+        Message::Binary(b) => {
+            return Ok(Incoming::Message(Box::new(
+                BinanceWebsocketMessage::Binary(b),
+            )))
+        }
+        Message::Pong(..) => return Ok(Incoming::Message(Box::new(BinanceWebsocketMessage::Pong))),
+        Message::Ping(..) => return Ok(Incoming::Message(Box::new(BinanceWebsocketMessage::Ping))),
     };
 
     trace!("Incoming websocket message {}", msg);
-    let message = match sub {
+    let (stream, data) = match from_str(&msg)? {
+        IncomingText::Stream(StreamMessage { stream, data }) => (stream, data),
+        IncomingText::Response(response) => return Ok(Incoming::Response(response)),
+    };
+    let sub = match subscriptions.get(&stream) {
+        Some(sub) => sub,
+        None => {
+            trace!("Message of unsubscribed stream '{}' ignored", stream);
+            return Ok(Incoming::Ignored);
+        }
+    };
+
+    let message = match *sub {
         BinanceSubscription::AggregateTrade(..) => {
-            BinanceWebsocketMessage::AggregateTrade(from_str(&msg)?)
+            BinanceWebsocketMessage::AggregateTrade(from_value(data)?)
         }
-        BinanceSubscription::Trade(..) => BinanceWebsocketMessage::Trade(from_str(&msg)?),
+        BinanceSubscription::Trade(..) => BinanceWebsocketMessage::Trade(from_value(data)?),
         BinanceSubscription::Candlestick(..) => {
-            BinanceWebsocketMessage::Candlestick(from_str(&msg)?)
+            BinanceWebsocketMessage::Candlestick(from_value(data)?)
+        }
+        BinanceSubscription::MiniTicker(..) => {
+            BinanceWebsocketMessage::MiniTicker(from_value(data)?)
         }
-        BinanceSubscription::MiniTicker(..) => BinanceWebsocketMessage::MiniTicker(from_str(&msg)?),
         BinanceSubscription::MiniTickerAll => {
-            BinanceWebsocketMessage::MiniTickerAll(from_str(&msg)?)
+            BinanceWebsocketMessage::MiniTickerAll(from_value(data)?)
         }
-        BinanceSubscription::Ticker(..) => BinanceWebsocketMessage::Ticker(from_str(&msg)?),
-        BinanceSubscription::TickerAll => BinanceWebsocketMessage::TickerAll(from_str(&msg)?),
-        BinanceSubscription::OrderBook(..) => BinanceWebsocketMessage::OrderBook(from_str(&msg)?),
-        BinanceSubscription::DiffDepth(..) => BinanceWebsocketMessage::DiffDepth(from_str(&msg)?),
+        BinanceSubscription::Ticker(..) => BinanceWebsocketMessage::Ticker(from_value(data)?),
+        BinanceSubscription::TickerAll => BinanceWebsocketMessage::TickerAll(from_value(data)?),
+        BinanceSubscription::OrderBook(..) => BinanceWebsocketMessage::OrderBook(from_value(data)?),
+        BinanceSubscription::DiffDepth(..) => BinanceWebsocketMessage::DiffDepth(from_value(data)?),
         BinanceSubscription::UserData(..) => {
-            let msg: Either<AccountUpdate, OrderUpdate> = from_str(&msg)?;
-            match msg {
-                Either::Left(m) => BinanceWebsocketMessage::AccountUpdate(m),
-                Either::Right(m) => BinanceWebsocketMessage::OrderUpdate(m),
+            let event = data["e"].as_str().unwrap_or_default().to_string();
+            match event.as_str() {
+                "outboundAccountPosition" => {
+                    BinanceWebsocketMessage::AccountUpdate(from_value(data)?)
+                }
+                "executionReport" => BinanceWebsocketMessage::OrderUpdate(from_value(data)?),
+                "listStatus" => BinanceWebsocketMessage::ListStatus(from_value(data)?),
+                "listenKeyExpired" => BinanceWebsocketMessage::ListenKeyExpired(from_value(data)?),
+                _ => {
+                    trace!("User data event '{}' ignored", event);
+                    return Ok(Incoming::Ignored);
+                }
             }
         }
     };
-    Ok(message)
+    Ok(Incoming::Message(Box::new(message)))
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
-enum Either<L, R> {
-    Left(L),
-    Right(R),
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::{err, ok, poll_fn};
+    use futures::sync::mpsc::{unbounded, UnboundedSender};
+    use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+    use serde_json::{from_str, json, Value};
+    use tungstenite::Message;
+
+    use super::{parse_message, stream_name, BinanceWebsocket, Connect, Connection, Incoming};
+    use crate::error::{BinanceError, Result};
+    use crate::model::websocket::{BinanceSubscription, BinanceWebsocketMessage};
+
+    // Requests written by the websocket on a fake connection
+    #[derive(Clone, Default)]
+    struct Sent(Arc<Mutex<Vec<Value>>>);
+
+    impl Sink for Sent {
+        type SinkItem = Message;
+        type SinkError = tungstenite::Error;
+
+        fn start_send(&mut self, msg: Message) -> StartSend<Message, tungstenite::Error> {
+            if let Message::Text(text) = msg {
+                self.0.lock().unwrap().push(from_str(&text).unwrap());
+            }
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), tungstenite::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    // Server end of a fake connection
+    struct Server {
+        streams: HashSet<String>,
+        sent: Sent,
+        incoming: UnboundedSender<Message>,
+    }
+
+    impl Server {
+        fn requests(&self) -> Vec<Value> {
+            self.sent.0.lock().unwrap().clone()
+        }
+
+        fn reply(&self, msg: String) {
+            self.incoming.unbounded_send(Message::Text(msg)).unwrap();
+        }
+    }
+
+    type Servers = Arc<Mutex<Vec<Server>>>;
+
+    // A websocket over fake connections, refusing to open the streams of "failbtc"
+    fn websocket() -> (BinanceWebsocket, Servers) {
+        let servers = Servers::default();
+        let opened = servers.clone();
+        let ws = BinanceWebsocket::with_connector(move |streams| -> Connect {
+            if streams.iter().any(|name| name.starts_with("failbtc")) {
+                return Box::new(err(format_err!("connection refused")));
+            }
+            let sent = Sent::default();
+            let (incoming, stream) = unbounded();
+            let stream = stream.map_err(|()| tungstenite::Error::Utf8);
+            let connection =
+                Connection::new(streams.clone(), Box::new(sent.clone()), Box::new(stream));
+            opened.lock().unwrap().push(Server {
+                streams,
+                sent,
+                incoming,
+            });
+            Box::new(ok(connection))
+        });
+        (ws, servers)
+    }
+
+    // Polls once within a task
+    fn poll<F: Future>(future: &mut F) -> Poll<F::Item, F::Error> {
+        poll_fn(|| Ok::<_, ()>(Async::Ready(future.poll())))
+            .wait()
+            .unwrap()
+    }
+
+    fn poll_stream<S: Stream>(stream: &mut S) -> Poll<Option<S::Item>, S::Error> {
+        poll_fn(|| Ok::<_, ()>(Async::Ready(stream.poll())))
+            .wait()
+            .unwrap()
+    }
+
+    fn trade(symbol: &str) -> BinanceSubscription {
+        BinanceSubscription::Trade(symbol.to_string())
+    }
+
+    #[test]
+    fn route_combined_stream() -> Result<()> {
+        let sub = BinanceSubscription::Trade("bnbbtc".to_string());
+        let mut subscriptions = HashMap::new();
+        subscriptions.insert(stream_name(&sub), sub);
+
+        let msg = r#"{"stream":"bnbbtc@trade","data":{"e":"trade","E":123456789,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","b":88,"a":50,"T":123456785,"m":true,"M":true}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Message(msg) => match *msg {
+                BinanceWebsocketMessage::Trade(trade) => assert_eq!(trade.trade_id, 12345),
+                _ => panic!("trade expected"),
+            },
+            _ => panic!("trade expected"),
+        }
+
+        let msg = r#"{"stream":"bnbbtc@aggTrade","data":{}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Ignored => {}
+            _ => panic!("message should be ignored"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn route_uppercase_symbol() -> Result<()> {
+        let sub = BinanceSubscription::Trade("BNBBTC".to_string());
+        let mut subscriptions = HashMap::new();
+        subscriptions.insert(stream_name(&sub), sub);
+
+        let msg = r#"{"stream":"bnbbtc@trade","data":{"e":"trade","E":123456789,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","T":123456785,"m":true,"M":true}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Message(msg) => match *msg {
+                BinanceWebsocketMessage::Trade(trade) => assert_eq!(trade.symbol, "BNBBTC"),
+                _ => panic!("trade expected"),
+            },
+            _ => panic!("trade expected"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn route_user_data() -> Result<()> {
+        let sub = BinanceSubscription::UserData("key".to_string());
+        let mut subscriptions = HashMap::new();
+        subscriptions.insert(stream_name(&sub), sub);
+
+        let msg = r#"{"stream":"key","data":{"e":"listStatus","E":1564035303637,"s":"ETHBTC","g":2,"c":"OCO","l":"EXEC_STARTED","L":"EXECUTING","r":"NONE","C":"F4QN4G8DlFATFlIUQ0cjdD","T":1564035303625,"O":[{"s":"ETHBTC","i":17,"c":"AJYsMjErWJesZvqlJCTUgL"}]}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Message(msg) => match *msg {
+                BinanceWebsocketMessage::ListStatus(list) => {
+                    assert_eq!(list.order_list_id, 2);
+                    assert_eq!(list.orders[0].order_id, 17);
+                }
+                _ => panic!("list status expected"),
+            },
+            _ => panic!("list status expected"),
+        }
+
+        let msg = r#"{"stream":"key","data":{"e":"outboundAccountPosition","E":1564034571105,"u":1564034571073,"B":[{"a":"ETH","f":"10000.000000","l":"0.000000"}]}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Message(msg) => match *msg {
+                BinanceWebsocketMessage::AccountUpdate(update) => {
+                    assert_eq!(update.last_update_time, 1564034571073);
+                    assert_eq!(update.balances[0].asset, "ETH");
+                }
+                _ => panic!("account update expected"),
+            },
+            _ => panic!("account update expected"),
+        }
+
+        let msg = r#"{"stream":"key","data":{"e":"listenKeyExpired","E":1576653824250}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Message(msg) => match *msg {
+                BinanceWebsocketMessage::ListenKeyExpired(expired) => {
+                    assert_eq!(expired.event_time, 1576653824250)
+                }
+                _ => panic!("listen key expiry expected"),
+            },
+            _ => panic!("listen key expiry expected"),
+        }
+
+        let msg = r#"{"stream":"key","data":{"e":"balanceUpdate","E":1573200697110}}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Ignored => {}
+            _ => panic!("unknown event should be ignored"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_response() -> Result<()> {
+        let subscriptions = HashMap::new();
+
+        let msg = r#"{"result":["btcusdt@aggTrade"],"id":3}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Response(response) => {
+                assert_eq!(response.id, 3);
+                assert!(response.error.is_none());
+            }
+            _ => panic!("response expected"),
+        }
+
+        let msg = r#"{"error":{"code":2,"msg":"Invalid request"},"id":4}"#;
+        match parse_message(&subscriptions, Message::Text(msg.into()))? {
+            Incoming::Response(response) => assert_eq!(response.error.unwrap().code, 2),
+            _ => panic!("response expected"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn chained_subscribes_share_connection() -> Result<()> {
+        let (ws, servers) = websocket();
+        let mut ws = ws
+            .subscribe(trade("BNBBTC"))
+            .and_then(|ws| ws.subscribe(BinanceSubscription::AggregateTrade("ethbtc".into())))
+            .wait()?;
+        let handle = ws.handle();
+        assert!(servers.lock().unwrap().is_empty());
+
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        {
+            let servers = servers.lock().unwrap();
+            assert_eq!(servers.len(), 1);
+            let mut streams: Vec<_> = servers[0].streams.iter().cloned().collect();
+            streams.sort();
+            assert_eq!(streams, vec!["bnbbtc@trade", "ethbtc@aggTrade"]);
+        }
+
+        // Later subscriptions are sent on the open connection
+        let mut subscribed = handle.subscribe(vec![trade("xrpbtc")]);
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        assert!(poll(&mut subscribed)?.is_not_ready());
+        {
+            let servers = servers.lock().unwrap();
+            assert_eq!(servers.len(), 1);
+            let request = &servers[0].requests()[0];
+            assert_eq!(request["method"], "SUBSCRIBE");
+            assert_eq!(request["params"], json!(["xrpbtc@trade"]));
+            servers[0].reply(format!(r#"{{"result":null,"id":{}}}"#, request["id"]));
+        }
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        assert!(poll(&mut subscribed)?.is_ready());
+        Ok(())
+    }
+
+    #[test]
+    fn refused_subscribe_fails_stream() {
+        let (ws, _) = websocket();
+        let mut ws = ws.subscribe(trade("failbtc")).wait().unwrap();
+        assert!(poll_stream(&mut ws).is_err());
+        assert!(ws.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn rejected_subscribe_rolled_back() -> Result<()> {
+        let (ws, servers) = websocket();
+        let mut ws = ws.subscribe(trade("bnbbtc")).wait()?;
+        let handle = ws.handle();
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+
+        let mut subscribed = handle.subscribe(vec![trade("ethbtc")]);
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        {
+            let servers = servers.lock().unwrap();
+            let request = &servers[0].requests()[0];
+            assert_eq!(request["params"], json!(["ethbtc@trade"]));
+            servers[0].reply(format!(
+                r#"{{"error":{{"code":2,"msg":"Invalid request"}},"id":{}}}"#,
+                request["id"]
+            ));
+        }
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        assert!(poll(&mut subscribed).is_err());
+        assert!(!ws.subscriptions.contains_key("ethbtc@trade"));
+        assert!(!ws.connections[0].streams.contains("ethbtc@trade"));
+
+        // Subscribing again asks the server again
+        let _subscribed = handle.subscribe(vec![trade("ethbtc")]);
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        let requests = servers.lock().unwrap()[0].requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1]["params"], json!(["ethbtc@trade"]));
+        Ok(())
+    }
+
+    #[test]
+    fn unsubscribe_last_stream_fails_pending_requests() -> Result<()> {
+        let (ws, servers) = websocket();
+        let mut ws = ws.subscribe(trade("bnbbtc")).wait()?;
+        let handle = ws.handle();
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+
+        let mut listed = handle.list_subscriptions();
+        assert!(poll_stream(&mut ws)?.is_not_ready());
+        assert_eq!(
+            servers.lock().unwrap()[0].requests()[0]["method"],
+            "LIST_SUBSCRIPTIONS"
+        );
+
+        // The connection is dropped before answering
+        let mut unsubscribed = handle.unsubscribe(vec![trade("bnbbtc")]);
+        assert!(poll_stream(&mut ws).is_err());
+        assert!(poll(&mut unsubscribed)?.is_ready());
+        let e = poll(&mut listed).unwrap_err();
+        match e.downcast_ref::<BinanceError>() {
+            Some(BinanceError::WebsocketClosed) => {}
+            _ => panic!("WebsocketClosed expected, got {}", e),
+        }
+        Ok(())
+    }
 }
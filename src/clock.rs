@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+// Offset of the server clock from the local one, shared by all clones of a `Transport`.
+// When a refresh interval is set, signed requests resync the offset once it is older than that.
+#[derive(Debug, Clone)]
+pub(crate) struct ServerClock {
+    refresh: Option<Duration>,
+    offset: Arc<AtomicI64>,
+    synced_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl ServerClock {
+    pub fn new(refresh: Option<Duration>) -> Self {
+        ServerClock {
+            refresh,
+            offset: Arc::new(AtomicI64::new(0)),
+            synced_at: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Server time in ms since the epoch, as estimated from the local clock
+    pub fn now(&self) -> i64 {
+        Utc::now().timestamp_millis() + self.offset()
+    }
+
+    // Server time minus local time, in ms
+    pub fn offset(&self) -> i64 {
+        self.offset.load(Ordering::Relaxed)
+    }
+
+    // Returns `true` if the offset is due for a refresh, in which case the caller is expected to
+    // sync it: concurrent callers get `false` until the sync is recorded or released.
+    pub fn claim_sync(&self) -> bool {
+        let refresh = match self.refresh {
+            Some(refresh) => refresh,
+            None => return false,
+        };
+        let mut synced_at = self.synced_at.lock().unwrap();
+        match *synced_at {
+            Some(at) if at.elapsed() < refresh => false,
+            _ => {
+                *synced_at = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    // Makes the next `claim_sync` succeed again, after a failed sync
+    pub fn release_sync(&self) {
+        *self.synced_at.lock().unwrap() = None;
+    }
+
+    // Records a server time measured between `sent` and `received` (local ms since the epoch).
+    // The server is assumed to have stamped it halfway through the round trip.
+    pub fn record(&self, sent: i64, server_time: i64, received: i64) -> i64 {
+        let offset = server_time - (sent + received) / 2;
+        self.offset.store(offset, Ordering::Relaxed);
+        *self.synced_at.lock().unwrap() = Some(Instant::now());
+        debug!(
+            "[ServerClock] Offset {} ms, round trip {} ms",
+            offset,
+            received - sent
+        );
+        offset
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ServerClock;
+
+    #[test]
+    fn record_offset() {
+        let clock = ServerClock::new(Some(Duration::from_secs(60)));
+        assert!(clock.claim_sync());
+        assert!(!clock.claim_sync());
+
+        assert_eq!(clock.record(1_000, 1_600, 1_200), 500);
+        assert_eq!(clock.offset(), 500);
+        assert!(!clock.claim_sync());
+
+        clock.release_sync();
+        assert!(clock.claim_sync());
+        assert!(!ServerClock::new(None).claim_sync());
+    }
+}
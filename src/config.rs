@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use rand::{thread_rng, Rng};
+
+use ratelimit::RateLimitPolicy;
+
+static PRODUCTION_REST: &str = "https://api.binance.com";
+static PRODUCTION_WS: &str = "wss://stream.binance.com:9443";
+static TESTNET_REST: &str = "https://testnet.binance.vision";
+static TESTNET_WS: &str = "wss://testnet.binance.vision";
+static BINANCE_US_REST: &str = "https://api.binance.us";
+static BINANCE_US_WS: &str = "wss://stream.binance.us:9443";
+
+// Endpoints used by a `Binance` instance.
+// The websocket endpoint is the stream host without a path, e.g. "wss://stream.binance.com:9443".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub rest_api_endpoint: String,
+    pub ws_endpoint: String,
+    pub rate_limit_policy: RateLimitPolicy,
+    // Resync the server clock offset used by signed requests this often, never if `None`
+    pub time_sync_interval: Option<Duration>,
+    pub retry_policy: RetryPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::production()
+    }
+}
+
+impl Config {
+    pub fn production() -> Self {
+        Config {
+            rest_api_endpoint: PRODUCTION_REST.into(),
+            ws_endpoint: PRODUCTION_WS.into(),
+            rate_limit_policy: RateLimitPolicy::default(),
+            time_sync_interval: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    // Spot test network: https://testnet.binance.vision
+    pub fn testnet() -> Self {
+        Config {
+            rest_api_endpoint: TESTNET_REST.into(),
+            ws_endpoint: TESTNET_WS.into(),
+            rate_limit_policy: RateLimitPolicy::default(),
+            time_sync_interval: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn binance_us() -> Self {
+        Config {
+            rest_api_endpoint: BINANCE_US_REST.into(),
+            ws_endpoint: BINANCE_US_WS.into(),
+            rate_limit_policy: RateLimitPolicy::default(),
+            time_sync_interval: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn set_rest_api_endpoint<S: Into<String>>(mut self, endpoint: S) -> Self {
+        self.rest_api_endpoint = endpoint.into();
+        self
+    }
+
+    pub fn set_ws_endpoint<S: Into<String>>(mut self, endpoint: S) -> Self {
+        self.ws_endpoint = endpoint.into();
+        self
+    }
+
+    pub fn set_rate_limit_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.rate_limit_policy = policy;
+        self
+    }
+
+    pub fn set_time_sync_interval(mut self, interval: Option<Duration>) -> Self {
+        self.time_sync_interval = interval;
+        self
+    }
+
+    pub fn set_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+}
+
+// Exponential backoff: `initial`, then multiplied by `factor` after every failed attempt, up to `max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(60),
+            factor: 2,
+        }
+    }
+}
+
+impl Backoff {
+    // Delay before the retry following `attempt` failed attempts
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let mut delay = self.initial;
+        for _ in 0..attempt {
+            delay = match delay.checked_mul(self.factor) {
+                Some(delay) if delay < self.max => delay,
+                _ => return self.max,
+            };
+        }
+        delay.min(self.max)
+    }
+}
+
+// Retries of requests failing with a transient error: transport failures, 5xx responses,
+// or Binance reporting a disconnection or timeout of its backend.
+// GET requests are retried; new orders only when they carry a newClientOrderId.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Backoff,
+    // Upper bound of a random delay added to the backoff
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            backoff: Backoff {
+                initial: Duration::from_millis(200),
+                max: Duration::from_secs(5),
+                factor: 2,
+            },
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    // Delay before the retry following `attempt` failed attempts
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let jitter = self.jitter.as_secs() * 1000 + u64::from(self.jitter.subsec_millis());
+        let jitter = if jitter > 0 {
+            thread_rng().gen_range(0, jitter)
+        } else {
+            0
+        };
+        self.backoff.delay(attempt) + Duration::from_millis(jitter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Backoff, RetryPolicy};
+
+    #[test]
+    fn backoff_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            factor: 3,
+        };
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(900));
+        assert_eq!(backoff.delay(3), Duration::from_secs(1));
+        assert_eq!(backoff.delay(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn retry_delay_jitter() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff: Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(1),
+                factor: 2,
+            },
+            jitter: Duration::from_millis(50),
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(200));
+            assert!(delay < Duration::from_millis(250));
+        }
+
+        let policy = RetryPolicy {
+            jitter: Duration::from_millis(0),
+            ..policy
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+    }
+}
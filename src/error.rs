@@ -2,6 +2,7 @@ use std::result::Result as StdResult;
 
 use failure::Error;
 use serde::Deserialize;
+use serde_json::Value;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -10,6 +11,9 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 pub struct BinanceResponseError {
     pub code: i64,
     pub msg: String,
+    // Details of some errors, e.g. the outcome of both sides of a failed cancel-replace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -34,8 +38,201 @@ pub enum BinanceError {
     AssetsNotFound,
     #[fail(display = "Symbol not found")]
     SymbolNotFound,
+    #[fail(display = "Symbol registry not loaded")]
+    RegistryNotLoaded,
     #[fail(display = "No Api key set for private api")]
     NoApiKeySet,
     #[fail(display = "No stream is subscribed")]
     NoStreamSubscribed,
+    #[fail(display = "Websocket is closed")]
+    WebsocketClosed,
+    #[fail(display = "Invalid order: {}", _0)]
+    InvalidOrder(String),
+    #[fail(display = "Invalid kline interval: {}", _0)]
+    InvalidKlineInterval(String),
+    #[fail(display = "Invalid ticker window size: {}", _0)]
+    InvalidWindowSize(String),
+    #[fail(display = "Too many symbols: {}, at most {}", _0, _1)]
+    TooManySymbols(usize, usize),
+    #[fail(display = "Order rejected by {}: {}", filter, reason)]
+    FilterRejected { filter: String, reason: String },
+    #[fail(display = "Rate limit reached, retry after {} ms", retry_after)]
+    RateLimited { retry_after: u64 },
+    // The request could not be sent or the response not received
+    #[fail(display = "Transport error: {}", _0)]
+    Transport(String),
+    // Non success status without a Binance error in the body, e.g. a 502 page from a proxy
+    #[fail(display = "HTTP status {}: {}", status, body)]
+    Http { status: u16, body: String },
+    // 429 (too many requests) or 418 (IP banned), `retry_after` in seconds
+    #[fail(
+        display = "HTTP status {}, retry after {:?} s: {}",
+        status, retry_after, msg
+    )]
+    TooManyRequests {
+        status: u16,
+        retry_after: Option<u64>,
+        msg: String,
+    },
+    // The response body is not what the endpoint returns
+    #[fail(display = "Cannot decode response: {}, body: {}", reason, body)]
+    Decode { reason: String, body: String },
+    #[fail(display = "Binance returns error {:?}: {}", code, msg)]
+    Api {
+        code: ApiErrorCode,
+        msg: String,
+        data: Option<Value>,
+    },
+}
+
+impl From<BinanceResponseError> for BinanceError {
+    fn from(e: BinanceResponseError) -> Self {
+        BinanceError::Api {
+            code: ApiErrorCode::from_response(e.code, &e.msg),
+            msg: e.msg,
+            data: e.data,
+        }
+    }
+}
+
+// Error codes of the REST api
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/errors.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiErrorCode {
+    Unknown,
+    Disconnected,
+    Unauthorized,
+    TooManyRequests,
+    UnexpectedResponse,
+    Timeout,
+    InvalidMessage,
+    UnknownOrderComposition,
+    TooManyOrders,
+    ServiceShuttingDown,
+    UnsupportedOperation,
+    InvalidTimestamp,
+    InvalidSignature,
+    IllegalChars,
+    TooManyParameters,
+    MandatoryParamEmptyOrMalformed,
+    UnknownParam,
+    UnreadParameters,
+    ParamEmpty,
+    ParamNotRequired,
+    BadPrecision,
+    NoDepth,
+    TifNotRequired,
+    InvalidTif,
+    InvalidOrderType,
+    InvalidSide,
+    EmptyNewClOrdId,
+    EmptyOrgClOrdId,
+    BadInterval,
+    BadSymbol,
+    InvalidListenKey,
+    MoreThanXxHours,
+    OptionalParamsBadCombo,
+    InvalidParameter,
+    // -2010 with a balance related message
+    InsufficientBalance,
+    NewOrderRejected,
+    CancelRejected,
+    // -2011 or -2013 for an order that does not exist
+    UnknownOrder,
+    BadApiKeyFormat,
+    RejectedMbxKey,
+    NoTradingWindow,
+    // -2021: one of the cancel and the new order of a cancel-replace failed
+    CancelReplacePartiallyFailed,
+    // -2022: both the cancel and the new order of a cancel-replace failed
+    CancelReplaceFailed,
+    // Any other code
+    Other(i64),
+}
+
+impl ApiErrorCode {
+    pub fn from_response(code: i64, msg: &str) -> Self {
+        use self::ApiErrorCode::*;
+
+        match code {
+            -1000 => Unknown,
+            -1001 => Disconnected,
+            -1002 => Unauthorized,
+            -1003 => TooManyRequests,
+            -1006 => UnexpectedResponse,
+            -1007 => Timeout,
+            -1013 => InvalidMessage,
+            -1014 => UnknownOrderComposition,
+            -1015 => TooManyOrders,
+            -1016 => ServiceShuttingDown,
+            -1020 => UnsupportedOperation,
+            -1021 => InvalidTimestamp,
+            -1022 => InvalidSignature,
+            -1100 => IllegalChars,
+            -1101 => TooManyParameters,
+            -1102 => MandatoryParamEmptyOrMalformed,
+            -1103 => UnknownParam,
+            -1104 => UnreadParameters,
+            -1105 => ParamEmpty,
+            -1106 => ParamNotRequired,
+            -1111 => BadPrecision,
+            -1112 => NoDepth,
+            -1114 => TifNotRequired,
+            -1115 => InvalidTif,
+            -1116 => InvalidOrderType,
+            -1117 => InvalidSide,
+            -1118 => EmptyNewClOrdId,
+            -1119 => EmptyOrgClOrdId,
+            -1120 => BadInterval,
+            -1121 => BadSymbol,
+            -1125 => InvalidListenKey,
+            -1127 => MoreThanXxHours,
+            -1128 => OptionalParamsBadCombo,
+            -1130 => InvalidParameter,
+            -2010 if msg.to_lowercase().contains("insufficient balance") => InsufficientBalance,
+            -2010 => NewOrderRejected,
+            -2011 if msg.starts_with("Unknown order") => UnknownOrder,
+            -2011 => CancelRejected,
+            -2013 => UnknownOrder,
+            -2014 => BadApiKeyFormat,
+            -2015 => RejectedMbxKey,
+            -2016 => NoTradingWindow,
+            -2021 => CancelReplacePartiallyFailed,
+            -2022 => CancelReplaceFailed,
+            code => Other(code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ApiErrorCode, BinanceError, BinanceResponseError};
+
+    #[test]
+    fn api_error_codes() {
+        let e = BinanceResponseError {
+            code: -2010,
+            msg: "Account has insufficient balance for requested action.".into(),
+            data: None,
+        };
+        match BinanceError::from(e) {
+            BinanceError::Api { code, .. } => assert_eq!(code, ApiErrorCode::InsufficientBalance),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(
+            ApiErrorCode::from_response(-2011, "Unknown order sent."),
+            ApiErrorCode::UnknownOrder
+        );
+        assert_eq!(
+            ApiErrorCode::from_response(
+                -1021,
+                "Timestamp for this request is outside of the recvWindow."
+            ),
+            ApiErrorCode::InvalidTimestamp
+        );
+        assert_eq!(
+            ApiErrorCode::from_response(-9999, ""),
+            ApiErrorCode::Other(-9999)
+        );
+    }
 }
@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rust_decimal::RoundingStrategy;
+
+use decimal::Decimal;
+use error::{BinanceError, Result};
+use model::order::{NewOcoOrder, NewOrder};
+use model::{ExchangeInfoMsg, OrderSide, OrderType, SymbolFilter, SymbolMsg};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Floor,
+    Ceil,
+    // Half way values are rounded away from zero
+    Nearest,
+}
+
+impl Rounding {
+    fn apply(self, value: Decimal) -> Decimal {
+        match self {
+            Rounding::Floor => value.floor(),
+            Rounding::Ceil => value.ceil(),
+            Rounding::Nearest => value.round_dp_with_strategy(0, RoundingStrategy::RoundHalfUp),
+        }
+    }
+
+    fn apply_dp(self, value: Decimal, dp: u32) -> Decimal {
+        match self {
+            Rounding::Nearest => value.round_dp_with_strategy(dp, RoundingStrategy::RoundHalfUp),
+            Rounding::Floor | Rounding::Ceil => {
+                // Only the fractional part is scaled, so that large values cannot overflow
+                let int = value.trunc();
+                let unit = Decimal::new(1, dp);
+                int + self.apply((value - int) / unit) * unit
+            }
+        }
+    }
+}
+
+// Rounds `value` to the grid `min + n * step` used by the PRICE_FILTER and LOT_SIZE filters,
+// then to `precision` decimal places. A zero step only rounds to the precision.
+pub fn round_to_step(
+    value: Decimal,
+    min: Decimal,
+    step: Decimal,
+    precision: u64,
+    rounding: Rounding,
+) -> Decimal {
+    let zero = Decimal::from(0);
+    let value = if step == zero {
+        value
+    } else {
+        min + rounding.apply((value - min) / step) * step
+    };
+
+    // Decimal holds at most 28 decimal places
+    rounding
+        .apply_dp(value, precision.min(28) as u32)
+        .normalize()
+}
+
+impl SymbolMsg {
+    // Rounds a price to the tick size, and to the precision of the quote asset
+    pub fn round_price(&self, price: Decimal, rounding: Rounding) -> Decimal {
+        let (min, step) = self
+            .filters
+            .iter()
+            .filter_map(|f| match *f {
+                SymbolFilter::PriceFilter {
+                    min_price,
+                    tick_size,
+                    ..
+                } => Some((min_price, tick_size)),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_default();
+        round_to_step(price, min, step, self.quote_precision, rounding)
+    }
+
+    // Rounds a quantity to the LOT_SIZE step size, and to the precision of the base asset
+    pub fn round_qty(&self, qty: Decimal, rounding: Rounding) -> Decimal {
+        let (min, step) = self
+            .filters
+            .iter()
+            .filter_map(|f| match *f {
+                SymbolFilter::LotSize {
+                    min_qty, step_size, ..
+                } => Some((min_qty, step_size)),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_default();
+        round_to_step(qty, min, step, self.base_asset_precision, rounding)
+    }
+
+    // Rounds the quantity of a MARKET order, which also has to match MARKET_LOT_SIZE
+    pub fn round_market_qty(&self, qty: Decimal, rounding: Rounding) -> Decimal {
+        let qty = self.round_qty(qty, rounding);
+        let market = self.filters.iter().find_map(|f| match *f {
+            SymbolFilter::MarketLotSize {
+                min_qty, step_size, ..
+            } => Some((min_qty, step_size)),
+            _ => None,
+        });
+        match market {
+            Some((min, step)) => round_to_step(qty, min, step, self.base_asset_precision, rounding),
+            None => qty,
+        }
+    }
+}
+
+// Checks orders against the symbol filters of a cached exchange info,
+// reporting the filter that would make the exchange reject them with -1013.
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/rest-api.md#filters
+#[derive(Debug, Clone)]
+pub struct OrderValidator {
+    symbols: Arc<HashMap<String, SymbolMsg>>,
+}
+
+impl OrderValidator {
+    pub fn new(exchange_info: &ExchangeInfoMsg) -> Self {
+        OrderValidator {
+            symbols: Arc::new(
+                exchange_info
+                    .symbols
+                    .iter()
+                    .map(|s| (s.symbol.clone(), s.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn symbol(&self, symbol: &str) -> Option<&SymbolMsg> {
+        self.symbols.get(symbol)
+    }
+
+    // Rounds the prices and quantities of an order to the grid of its symbol.
+    // Quantities are rounded down, and prices away from the market: down for BUY, up for SELL.
+    // Orders of unknown symbols are returned unchanged.
+    pub fn normalize(&self, mut order: NewOrder) -> NewOrder {
+        let symbol = match self.symbols.get(&order.symbol) {
+            Some(symbol) => symbol,
+            None => return order,
+        };
+
+        let price_rounding = match order.side {
+            OrderSide::Buy => Rounding::Floor,
+            OrderSide::Sell => Rounding::Ceil,
+        };
+        order.price = order.price.map(|p| symbol.round_price(p, price_rounding));
+        order.stop_price = order
+            .stop_price
+            .map(|p| symbol.round_price(p, price_rounding));
+        order.quantity = order.quantity.map(|q| match order.order_type {
+            OrderType::Market => symbol.round_market_qty(q, Rounding::Floor),
+            _ => symbol.round_qty(q, Rounding::Floor),
+        });
+        order.iceberg_qty = order
+            .iceberg_qty
+            .map(|q| symbol.round_qty(q, Rounding::Floor));
+        order.quote_order_qty = order.quote_order_qty.map(|q| {
+            round_to_step(
+                q,
+                0.into(),
+                0.into(),
+                symbol.quote_precision,
+                Rounding::Floor,
+            )
+        });
+        order
+    }
+
+    // Validates an order without market data: the PERCENT_PRICE filter and the
+    // MIN_NOTIONAL and NOTIONAL filters of MARKET orders are skipped.
+    pub fn validate(&self, order: &NewOrder) -> Result<()> {
+        self.validate_with_avg_price(order, None)
+    }
+
+    // Validates both orders of an OCO order list
+    pub fn validate_oco(&self, order: &NewOcoOrder) -> Result<()> {
+        if let Some(symbol) = self.symbols.get(&order.symbol) {
+            if !symbol.oco_allowed {
+                rejected("OCO_ALLOWED", "OCO orders are not allowed".into())?
+            }
+        }
+        let (limit, stop) = order.legs();
+        self.validate(&limit)?;
+        self.validate(&stop)
+    }
+
+    // Validates an order, using `avg_price` (see `get_average_price`) for the filters
+    // relative to the average price of the symbol.
+    pub fn validate_with_avg_price(
+        &self,
+        order: &NewOrder,
+        avg_price: Option<Decimal>,
+    ) -> Result<()> {
+        let symbol = match self.symbols.get(&order.symbol) {
+            Some(symbol) => symbol,
+            None => Err(BinanceError::SymbolNotFound)?,
+        };
+
+        if !symbol.order_types.contains(&order.order_type) {
+            rejected(
+                "ORDER_TYPES",
+                format!("{:?} orders are not allowed", order.order_type),
+            )?
+        }
+        if order.iceberg_qty.is_some() && !symbol.iceberg_allowed {
+            rejected("ICEBERG_ALLOWED", "iceberg orders are not allowed".into())?
+        }
+
+        for filter in &symbol.filters {
+            check_filter(filter, order, avg_price)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_filter(filter: &SymbolFilter, order: &NewOrder, avg_price: Option<Decimal>) -> Result<()> {
+    let zero = Decimal::from(0);
+    let market = order.order_type == OrderType::Market;
+
+    match *filter {
+        SymbolFilter::PriceFilter {
+            min_price,
+            max_price,
+            tick_size,
+        } => {
+            let prices = [("price", order.price), ("stopPrice", order.stop_price)];
+            for (name, price) in prices.iter() {
+                let price = match *price {
+                    Some(price) => price,
+                    None => continue,
+                };
+                if min_price != zero && price < min_price {
+                    rejected(
+                        "PRICE_FILTER",
+                        format!("{} {} is below the minimum {}", name, price, min_price),
+                    )?
+                }
+                if max_price != zero && price > max_price {
+                    rejected(
+                        "PRICE_FILTER",
+                        format!("{} {} is above the maximum {}", name, price, max_price),
+                    )?
+                }
+                if tick_size != zero && (price - min_price) % tick_size != zero {
+                    rejected(
+                        "PRICE_FILTER",
+                        format!(
+                            "{} {} is not a multiple of the tick size {}",
+                            name, price, tick_size
+                        ),
+                    )?
+                }
+            }
+        }
+        SymbolFilter::PercentPrice {
+            multiplier_up,
+            multiplier_down,
+            ..
+        } => {
+            let avg_price = match avg_price {
+                Some(avg_price) => avg_price,
+                None => return Ok(()),
+            };
+            // The stop price of STOP_LOSS(_LIMIT) and TAKE_PROFIT(_LIMIT) orders is checked too
+            let prices = [("price", order.price), ("stopPrice", order.stop_price)];
+            for (name, price) in prices.iter() {
+                let price = match *price {
+                    Some(price) => price,
+                    None => continue,
+                };
+                if price > avg_price * multiplier_up || price < avg_price * multiplier_down {
+                    rejected(
+                        "PERCENT_PRICE",
+                        format!(
+                            "{} {} is outside [{}, {}] of the average price {}",
+                            name, price, multiplier_down, multiplier_up, avg_price
+                        ),
+                    )?
+                }
+            }
+        }
+        SymbolFilter::LotSize {
+            min_qty,
+            max_qty,
+            step_size,
+        } => check_qty("LOT_SIZE", order.quantity, min_qty, max_qty, step_size)?,
+        SymbolFilter::MarketLotSize {
+            min_qty,
+            max_qty,
+            step_size,
+        } if market => check_qty(
+            "MARKET_LOT_SIZE",
+            order.quantity,
+            min_qty,
+            max_qty,
+            step_size,
+        )?,
+        SymbolFilter::MinNotional {
+            min_notional,
+            apply_to_market,
+            ..
+        } => {
+            if let Some(notional) = notional(order, market, apply_to_market, avg_price) {
+                if notional < min_notional {
+                    rejected(
+                        "MIN_NOTIONAL",
+                        format!(
+                            "notional {} is below the minimum {}",
+                            notional, min_notional
+                        ),
+                    )?
+                }
+            }
+        }
+        SymbolFilter::Notional {
+            min_notional,
+            apply_min_to_market,
+            max_notional,
+            apply_max_to_market,
+            ..
+        } => {
+            if let Some(notional) = notional(order, market, apply_min_to_market, avg_price) {
+                if notional < min_notional {
+                    rejected(
+                        "NOTIONAL",
+                        format!(
+                            "notional {} is below the minimum {}",
+                            notional, min_notional
+                        ),
+                    )?
+                }
+            }
+            if let Some(notional) = notional(order, market, apply_max_to_market, avg_price) {
+                if max_notional != zero && notional > max_notional {
+                    rejected(
+                        "NOTIONAL",
+                        format!(
+                            "notional {} is above the maximum {}",
+                            notional, max_notional
+                        ),
+                    )?
+                }
+            }
+        }
+        SymbolFilter::IcebergParts { limit } => {
+            if let (Some(qty), Some(iceberg_qty)) = (order.quantity, order.iceberg_qty) {
+                let parts = (qty / iceberg_qty).ceil();
+                if parts > Decimal::from(limit) {
+                    rejected(
+                        "ICEBERG_PARTS",
+                        format!("{} iceberg parts exceed the limit of {}", parts, limit),
+                    )?
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Value of an order in the quote asset, if known and the filter applies to its type
+fn notional(
+    order: &NewOrder,
+    market: bool,
+    apply_to_market: bool,
+    avg_price: Option<Decimal>,
+) -> Option<Decimal> {
+    if market {
+        match (
+            apply_to_market,
+            order.quote_order_qty,
+            order.quantity,
+            avg_price,
+        ) {
+            (false, _, _, _) => None,
+            (true, Some(quote_qty), _, _) => Some(quote_qty),
+            (true, None, Some(qty), Some(avg_price)) => Some(qty * avg_price),
+            _ => None,
+        }
+    } else {
+        match (order.price, order.quantity) {
+            (Some(price), Some(qty)) => Some(price * qty),
+            _ => None,
+        }
+    }
+}
+
+fn check_qty(
+    filter: &str,
+    qty: Option<Decimal>,
+    min_qty: Decimal,
+    max_qty: Decimal,
+    step_size: Decimal,
+) -> Result<()> {
+    let zero = Decimal::from(0);
+    let qty = match qty {
+        Some(qty) => qty,
+        None => return Ok(()),
+    };
+
+    if qty < min_qty {
+        rejected(
+            filter,
+            format!("quantity {} is below the minimum {}", qty, min_qty),
+        )?
+    }
+    if max_qty != zero && qty > max_qty {
+        rejected(
+            filter,
+            format!("quantity {} is above the maximum {}", qty, max_qty),
+        )?
+    }
+    if step_size != zero && (qty - min_qty) % step_size != zero {
+        rejected(
+            filter,
+            format!(
+                "quantity {} is not a multiple of the step size {}",
+                qty, step_size
+            ),
+        )?
+    }
+    Ok(())
+}
+
+fn rejected(filter: &str, reason: String) -> Result<()> {
+    Err(BinanceError::FilterRejected {
+        filter: filter.into(),
+        reason,
+    })?
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::from_str;
+
+    use super::{round_to_step, OrderValidator, Rounding};
+    use error::{BinanceError, Result};
+    use model::order::{NewOcoOrder, NewOrder};
+    use model::{ExchangeInfoMsg, OrderSide, OrderTimeInForce, OrderType};
+
+    fn validator() -> Result<OrderValidator> {
+        let info: ExchangeInfoMsg = from_str(
+            r#"{"timezone":"UTC","serverTime":1565246363776,"rateLimits":[],"exchangeFilters":[],
+                "symbols":[{"symbol":"BNBBTC","status":"TRADING","baseAsset":"BNB","baseAssetPrecision":8,
+                "quoteAsset":"BTC","quotePrecision":8,"orderTypes":["LIMIT","MARKET","STOP_LOSS_LIMIT"],"icebergAllowed":true,
+                "filters":[
+                    {"filterType":"PRICE_FILTER","minPrice":"0.00000010","maxPrice":"100000.00000000","tickSize":"0.00000010"},
+                    {"filterType":"PERCENT_PRICE","multiplierUp":"5","multiplierDown":"0.2","avgPriceMins":5},
+                    {"filterType":"LOT_SIZE","minQty":"0.01000000","maxQty":"90000000.00000000","stepSize":"0.01000000"},
+                    {"filterType":"MIN_NOTIONAL","minNotional":"0.00010000","applyToMarket":true,"avgPriceMins":5},
+                    {"filterType":"ICEBERG_PARTS","limit":10}]},
+                {"symbol":"ETHBTC","status":"TRADING","baseAsset":"ETH","baseAssetPrecision":8,
+                "quoteAsset":"BTC","quotePrecision":8,"quoteAssetPrecision":8,
+                "orderTypes":["LIMIT","LIMIT_MAKER","STOP_LOSS_LIMIT"],
+                "icebergAllowed":true,"ocoAllowed":true,"isSpotTradingAllowed":true,"permissions":["SPOT"],
+                "filters":[
+                    {"filterType":"NOTIONAL","minNotional":"0.00010000","applyMinToMarket":true,
+                     "maxNotional":"100.00000000","applyMaxToMarket":false,"avgPriceMins":5},
+                    {"filterType":"TRAILING_DELTA","minTrailingAboveDelta":10,"maxTrailingAboveDelta":2000,
+                     "minTrailingBelowDelta":10,"maxTrailingBelowDelta":2000}]}]}"#,
+        )?;
+        Ok(OrderValidator::new(&info))
+    }
+
+    fn rejected_by(result: Result<()>) -> String {
+        match result.map_err(|e| e.downcast::<BinanceError>()) {
+            Err(Ok(BinanceError::FilterRejected { filter, .. })) => filter,
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn filters() -> Result<()> {
+        let validator = validator()?;
+        let limit = |qty: &str, price: &str| -> Result<NewOrder> {
+            Ok(NewOrder::limit(
+                "BNBBTC",
+                OrderSide::Buy,
+                qty.parse()?,
+                price.parse()?,
+            ))
+        };
+
+        validator.validate(&limit("1.5", "0.0025")?)?;
+        validator.validate_with_avg_price(&limit("1.5", "0.0025")?, Some("0.0024".parse()?))?;
+
+        assert_eq!(
+            rejected_by(validator.validate(&limit("1.5", "0.00250005")?)),
+            "PRICE_FILTER"
+        );
+        assert_eq!(
+            rejected_by(validator.validate(&limit("1.505", "0.0025")?)),
+            "LOT_SIZE"
+        );
+        assert_eq!(
+            rejected_by(validator.validate(&limit("0.01", "0.0025")?)),
+            "MIN_NOTIONAL"
+        );
+        assert_eq!(
+            rejected_by(
+                validator.validate_with_avg_price(&limit("1.5", "0.0025")?, Some("0.02".parse()?))
+            ),
+            "PERCENT_PRICE"
+        );
+        let stop_loss_limit = |stop_price: &str| -> Result<NewOrder> {
+            Ok(
+                NewOrder::new("BNBBTC", OrderSide::Sell, OrderType::StopLossLimit)
+                    .time_in_force(OrderTimeInForce::Gtc)
+                    .quantity("1.5".parse()?)
+                    .price("0.0025".parse()?)
+                    .stop_price(stop_price.parse()?),
+            )
+        };
+        validator.validate_with_avg_price(&stop_loss_limit("0.0026")?, Some("0.0024".parse()?))?;
+        assert_eq!(
+            rejected_by(
+                validator
+                    .validate_with_avg_price(&stop_loss_limit("0.013")?, Some("0.0024".parse()?))
+            ),
+            "PERCENT_PRICE"
+        );
+        assert_eq!(
+            rejected_by(validator.validate(&limit("1.5", "0.0025")?.iceberg_qty("0.1".parse()?))),
+            "ICEBERG_PARTS"
+        );
+        assert!(validator
+            .validate(&NewOrder::market("ETHBTC", OrderSide::Buy, 1.into()))
+            .is_err());
+        let order = NewOrder::limit("ETHBTC", OrderSide::Buy, 1000.into(), 1.into());
+        assert_eq!(rejected_by(validator.validate(&order)), "NOTIONAL");
+        assert!(validator
+            .validate(&NewOrder::market("XRPBTC", OrderSide::Buy, 1.into()))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn oco_filters() -> Result<()> {
+        let validator = validator()?;
+        let oco = |symbol: &str, qty: &str| -> Result<NewOcoOrder> {
+            Ok(NewOcoOrder::new(
+                symbol,
+                OrderSide::Sell,
+                qty.parse()?,
+                "0.12".parse()?,
+                "0.09".parse()?,
+            )
+            .stop_limit_price("0.089".parse()?, OrderTimeInForce::Gtc))
+        };
+
+        validator.validate_oco(&oco("ETHBTC", "1")?)?;
+        assert_eq!(
+            rejected_by(validator.validate_oco(&oco("ETHBTC", "900")?)),
+            "NOTIONAL"
+        );
+        assert_eq!(
+            rejected_by(validator.validate_oco(&oco("BNBBTC", "1")?)),
+            "OCO_ALLOWED"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rounding() -> Result<()> {
+        let step = |value: &str, mode| -> Result<String> {
+            Ok(
+                round_to_step(value.parse()?, "0.01".parse()?, "0.05".parse()?, 8, mode)
+                    .to_string(),
+            )
+        };
+        assert_eq!(step("1.234", Rounding::Floor)?, "1.21");
+        assert_eq!(step("1.234", Rounding::Ceil)?, "1.26");
+        assert_eq!(step("1.235", Rounding::Nearest)?, "1.26");
+        assert_eq!(step("1.21", Rounding::Ceil)?, "1.21");
+        assert_eq!(
+            round_to_step(
+                "0.123456789".parse()?,
+                0.into(),
+                0.into(),
+                4,
+                Rounding::Floor
+            )
+            .to_string(),
+            "0.1234"
+        );
+        let large = "12345678901234567890.5".parse()?;
+        for (rounding, rounded) in [
+            (Rounding::Floor, "12345678901234567890"),
+            (Rounding::Ceil, "12345678901234567891"),
+            (Rounding::Nearest, "12345678901234567891"),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                round_to_step(large, 0.into(), 0.into(), 0, *rounding).to_string(),
+                *rounded
+            );
+            assert_eq!(
+                round_to_step(large, 0.into(), 0.into(), 18, *rounding),
+                large
+            );
+        }
+        assert_eq!(
+            round_to_step("-1.25".parse()?, 0.into(), 0.into(), 1, Rounding::Floor).to_string(),
+            "-1.3"
+        );
+
+        let validator = validator()?;
+        let order = validator.normalize(NewOrder::limit(
+            "BNBBTC",
+            OrderSide::Sell,
+            "1.5099".parse()?,
+            "0.00250005".parse()?,
+        ));
+        assert_eq!(order.quantity, Some("1.5".parse()?));
+        assert_eq!(order.price, Some("0.0025001".parse()?));
+        validator.validate(&order)?;
+        Ok(())
+    }
+}
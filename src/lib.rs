@@ -14,6 +14,7 @@ extern crate log;
 extern crate chrono;
 extern crate futures;
 extern crate hyper_tls;
+extern crate rand;
 extern crate result;
 extern crate rust_decimal;
 extern crate sugar;
@@ -23,9 +24,19 @@ extern crate tungstenite;
 extern crate url;
 
 mod client;
+mod clock;
+pub mod config;
 pub mod decimal;
 pub mod error;
+pub mod filter;
 pub mod model;
+pub mod orderbook;
+pub mod ratelimit;
+pub mod registry;
 mod transport;
 
-pub use client::Binance;
+pub use client::{
+    Binance, BinanceWebsocket, History, HistoryDirection, HistoryItem, HistoryQuery, KlineGap,
+    KlineHistory, SymbolCancel, UserDataStream, WebsocketHandle,
+};
+pub use config::Config;
@@ -1,6 +1,14 @@
+pub mod order;
 pub mod websocket;
 
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Deserialize;
+
 use decimal::Decimal;
+use error::{BinanceError, BinanceResponse};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -18,7 +26,13 @@ pub struct AccountInformationMsg {
     pub can_trade: bool,
     pub can_withdraw: bool,
     pub can_deposit: bool,
+    #[serde(default)]
+    pub update_time: u64,
+    #[serde(default)]
+    pub account_type: String,
     pub balances: Vec<BalanceMsg>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,13 +74,132 @@ pub struct OrderCanceledMsg {
     pub client_order_id: String,
 }
 
+// Order or order list canceled by canceling all the open orders of a symbol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CanceledOpenOrderMsg {
+    OrderList(OrderListMsg),
+    Order(OrderCanceledMsg),
+}
+
+// Response of a new order with newOrderRespType=ACK
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderAckMsg {
+    pub symbol: String,
+    pub order_id: i64,
+    pub client_order_id: String,
+    pub transact_time: u64,
+}
+
+pub type TransactionMsg = OrderAckMsg;
+
+// Response of a new order with newOrderRespType=RESULT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderResultMsg {
+    pub symbol: String,
+    pub order_id: i64,
+    pub client_order_id: String,
+    pub transact_time: u64,
+    pub price: Decimal,
+    pub orig_qty: Decimal,
+    pub executed_qty: Decimal,
+    pub cummulative_quote_qty: Decimal,
+    pub status: OrderStatus,
+    pub time_in_force: OrderTimeInForce,
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub side: OrderSide,
+}
+
+// Response of a new order with newOrderRespType=FULL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderFullMsg {
+    pub symbol: String,
+    pub order_id: i64,
+    pub client_order_id: String,
+    pub transact_time: u64,
+    pub price: Decimal,
+    pub orig_qty: Decimal,
+    pub executed_qty: Decimal,
+    pub cummulative_quote_qty: Decimal,
+    pub status: OrderStatus,
+    pub time_in_force: OrderTimeInForce,
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub side: OrderSide,
+    pub fills: Vec<FillMsg>,
+}
+
+// Outcome of a cancel-replace: each side is reported even if the other failed.
+// A response is `None` when its side was not attempted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelReplaceMsg<R> {
+    pub cancel_result: CancelReplaceResult,
+    pub new_order_result: CancelReplaceResult,
+    pub cancel_response: Option<BinanceResponse<OrderCanceledMsg>>,
+    pub new_order_response: Option<BinanceResponse<R>>,
+}
+
+// Order list, e.g. an OCO pair, as returned by the order list endpoints.
+// `order_reports` are only returned when placing or canceling the list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderListMsg {
+    pub order_list_id: i64,
+    pub contingency_type: ContingencyType,
+    pub list_status_type: ListStatusType,
+    pub list_order_status: ListOrderStatus,
+    pub list_client_order_id: String,
+    pub transaction_time: u64,
+    pub symbol: String,
+    pub orders: Vec<OrderListEntryMsg>,
+    #[serde(default)]
+    pub order_reports: Vec<OrderReportMsg>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderListEntryMsg {
+    pub symbol: String,
+    pub order_id: i64,
+    pub client_order_id: String,
+}
+
+// State of one order of a list after placing or canceling it
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct TransactionMsg {
+pub struct OrderReportMsg {
     pub symbol: String,
     pub order_id: i64,
+    pub order_list_id: i64,
     pub client_order_id: String,
+    // Set when canceling
+    pub orig_client_order_id: Option<String>,
     pub transact_time: u64,
+    pub price: Decimal,
+    pub orig_qty: Decimal,
+    pub executed_qty: Decimal,
+    pub cummulative_quote_qty: Decimal,
+    pub status: OrderStatus,
+    pub time_in_force: OrderTimeInForce,
+    #[serde(rename = "type")]
+    pub order_type: OrderType,
+    pub side: OrderSide,
+    pub stop_price: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FillMsg {
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub commission: Decimal,
+    pub commission_asset: String,
+    pub trade_id: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,8 +207,8 @@ pub struct BidMsg {
     pub price: Decimal,
     pub qty: Decimal,
 
-    // Never serialized.
-    #[serde(skip_serializing)]
+    // Never serialized. Absent from the diff depth stream.
+    #[serde(default, skip_serializing)]
     ignore: Vec<String>,
 }
 
@@ -84,8 +217,8 @@ pub struct AskMsg {
     pub price: Decimal,
     pub qty: Decimal,
 
-    // Never serialized.
-    #[serde(skip_serializing)]
+    // Never serialized. Absent from the diff depth stream.
+    #[serde(default, skip_serializing)]
     ignore: Vec<String>,
 }
 
@@ -143,6 +276,10 @@ pub struct PriceStatsMsg {
     pub high_price: Decimal,
     pub low_price: Decimal,
     pub volume: Decimal,
+    pub quote_volume: Decimal,
+    pub last_qty: Decimal,
+    pub bid_qty: Decimal,
+    pub ask_qty: Decimal,
     pub open_time: u64,
     pub close_time: u64,
     pub first_id: i64,
@@ -150,6 +287,64 @@ pub struct PriceStatsMsg {
     pub count: u64,
 }
 
+// Statistics of the rolling window ticker, unlike `PriceStatsMsg` without the order book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollingPriceStatsMsg {
+    pub symbol: String,
+    pub price_change: Decimal,
+    pub price_change_percent: Decimal,
+    pub weighted_avg_price: Decimal,
+    pub open_price: Decimal,
+    pub high_price: Decimal,
+    pub low_price: Decimal,
+    pub last_price: Decimal,
+    pub volume: Decimal,
+    pub quote_volume: Decimal,
+    pub open_time: u64,
+    pub close_time: u64,
+    pub first_id: i64,
+    pub last_id: i64,
+    pub count: u64,
+}
+
+// Window of the rolling ticker, from 1 minute to 7 days
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowSize {
+    Minutes(u8),
+    Hours(u8),
+    Days(u8),
+}
+
+impl WindowSize {
+    pub fn is_valid(self) -> bool {
+        match self {
+            WindowSize::Minutes(n) => (1..=59).contains(&n),
+            WindowSize::Hours(n) => (1..=23).contains(&n),
+            WindowSize::Days(n) => (1..=7).contains(&n),
+        }
+    }
+}
+
+impl fmt::Display for WindowSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WindowSize::Minutes(n) => write!(f, "{}m", n),
+            WindowSize::Hours(n) => write!(f, "{}h", n),
+            WindowSize::Days(n) => write!(f, "{}d", n),
+        }
+    }
+}
+
+// Average price over the last `mins` minutes, as used by the PERCENT_PRICE filter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvgPriceMsg {
+    pub mins: u64,
+    pub price: Decimal,
+}
+
+// Decoded from the array form returned by the klines endpoint
 #[derive(Debug, Clone)]
 pub struct KlineMsg {
     pub open_time: u64,
@@ -165,6 +360,235 @@ pub struct KlineMsg {
     pub taker_buy_quote_asset_volume: Decimal,
 }
 
+impl<'de> Deserialize<'de> for KlineMsg {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KlineVisitor;
+
+        impl<'de> Visitor<'de> for KlineVisitor {
+            type Value = KlineMsg;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a kline array")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<KlineMsg, A::Error> {
+                let kline = KlineMsg {
+                    open_time: element(&mut seq, 0)?,
+                    open: element(&mut seq, 1)?,
+                    high: element(&mut seq, 2)?,
+                    low: element(&mut seq, 3)?,
+                    close: element(&mut seq, 4)?,
+                    volume: element(&mut seq, 5)?,
+                    close_time: element(&mut seq, 6)?,
+                    quote_asset_volume: element(&mut seq, 7)?,
+                    number_of_trades: element(&mut seq, 8)?,
+                    taker_buy_base_asset_volume: element(&mut seq, 9)?,
+                    taker_buy_quote_asset_volume: element(&mut seq, 10)?,
+                };
+                // Unused trailing fields
+                while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+                Ok(kline)
+            }
+        }
+
+        deserializer.deserialize_seq(KlineVisitor)
+    }
+}
+
+fn element<'de, A, T>(seq: &mut A, index: usize) -> Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+{
+    seq.next_element()?
+        .ok_or_else(|| de::Error::invalid_length(index, &"11 kline fields"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KlineInterval {
+    #[serde(rename = "1s")]
+    Second1,
+    #[serde(rename = "1m")]
+    Minute1,
+    #[serde(rename = "3m")]
+    Minute3,
+    #[serde(rename = "5m")]
+    Minute5,
+    #[serde(rename = "15m")]
+    Minute15,
+    #[serde(rename = "30m")]
+    Minute30,
+    #[serde(rename = "1h")]
+    Hour1,
+    #[serde(rename = "2h")]
+    Hour2,
+    #[serde(rename = "4h")]
+    Hour4,
+    #[serde(rename = "6h")]
+    Hour6,
+    #[serde(rename = "8h")]
+    Hour8,
+    #[serde(rename = "12h")]
+    Hour12,
+    #[serde(rename = "1d")]
+    Day1,
+    #[serde(rename = "3d")]
+    Day3,
+    #[serde(rename = "1w")]
+    Week1,
+    #[serde(rename = "1M")]
+    Month1,
+}
+
+static DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+impl KlineInterval {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KlineInterval::Second1 => "1s",
+            KlineInterval::Minute1 => "1m",
+            KlineInterval::Minute3 => "3m",
+            KlineInterval::Minute5 => "5m",
+            KlineInterval::Minute15 => "15m",
+            KlineInterval::Minute30 => "30m",
+            KlineInterval::Hour1 => "1h",
+            KlineInterval::Hour2 => "2h",
+            KlineInterval::Hour4 => "4h",
+            KlineInterval::Hour6 => "6h",
+            KlineInterval::Hour8 => "8h",
+            KlineInterval::Hour12 => "12h",
+            KlineInterval::Day1 => "1d",
+            KlineInterval::Day3 => "3d",
+            KlineInterval::Week1 => "1w",
+            KlineInterval::Month1 => "1M",
+        }
+    }
+
+    // Length of the interval in ms, `None` for months which vary
+    pub fn millis(self) -> Option<u64> {
+        let minute = 60 * 1000;
+        let millis = match self {
+            KlineInterval::Second1 => 1000,
+            KlineInterval::Minute1 => minute,
+            KlineInterval::Minute3 => 3 * minute,
+            KlineInterval::Minute5 => 5 * minute,
+            KlineInterval::Minute15 => 15 * minute,
+            KlineInterval::Minute30 => 30 * minute,
+            KlineInterval::Hour1 => 60 * minute,
+            KlineInterval::Hour2 => 2 * 60 * minute,
+            KlineInterval::Hour4 => 4 * 60 * minute,
+            KlineInterval::Hour6 => 6 * 60 * minute,
+            KlineInterval::Hour8 => 8 * 60 * minute,
+            KlineInterval::Hour12 => 12 * 60 * minute,
+            KlineInterval::Day1 => DAY_MS,
+            KlineInterval::Day3 => 3 * DAY_MS,
+            KlineInterval::Week1 => 7 * DAY_MS,
+            KlineInterval::Month1 => return None,
+        };
+        Some(millis)
+    }
+
+    // Open time of the kline containing `time`, in ms since the epoch (UTC).
+    // Weeks open on monday, months on their first day.
+    pub fn open_time(self, time: u64) -> u64 {
+        match self {
+            KlineInterval::Month1 => {
+                let (year, month, _) = civil_from_days(time / DAY_MS);
+                days_from_civil(year, month, 1) * DAY_MS
+            }
+            // The epoch is a thursday
+            KlineInterval::Week1 => {
+                let monday = 4 * DAY_MS;
+                let week = 7 * DAY_MS;
+                ((time + week - monday) / week * week).saturating_sub(week - monday)
+            }
+            _ => {
+                let millis = self.millis().unwrap();
+                time / millis * millis
+            }
+        }
+    }
+
+    // Open time of the kline following the one containing `time`
+    pub fn next_open_time(self, time: u64) -> u64 {
+        match self {
+            KlineInterval::Month1 => {
+                let (year, month, _) = civil_from_days(time / DAY_MS);
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                days_from_civil(year, month, 1) * DAY_MS
+            }
+            _ => self.open_time(time) + self.millis().unwrap(),
+        }
+    }
+
+    // Close time of the kline containing `time`, one ms before the next opens
+    pub fn close_time(self, time: u64) -> u64 {
+        self.next_open_time(time) - 1
+    }
+}
+
+impl fmt::Display for KlineInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for KlineInterval {
+    type Err = BinanceError;
+
+    fn from_str(s: &str) -> Result<Self, BinanceError> {
+        let interval = match s {
+            "1s" => KlineInterval::Second1,
+            "1m" => KlineInterval::Minute1,
+            "3m" => KlineInterval::Minute3,
+            "5m" => KlineInterval::Minute5,
+            "15m" => KlineInterval::Minute15,
+            "30m" => KlineInterval::Minute30,
+            "1h" => KlineInterval::Hour1,
+            "2h" => KlineInterval::Hour2,
+            "4h" => KlineInterval::Hour4,
+            "6h" => KlineInterval::Hour6,
+            "8h" => KlineInterval::Hour8,
+            "12h" => KlineInterval::Hour12,
+            "1d" => KlineInterval::Day1,
+            "3d" => KlineInterval::Day3,
+            "1w" => KlineInterval::Week1,
+            "1M" => KlineInterval::Month1,
+            _ => return Err(BinanceError::InvalidKlineInterval(s.to_string())),
+        };
+        Ok(interval)
+    }
+}
+
+// (year, month, day) of a number of days since the epoch, see
+// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ExchangeInfoMsg {
@@ -178,17 +602,24 @@ pub struct ExchangeInfoMsg {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "filterType", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ExchangeFilter {
-    ExchangeMaxNumOrders { limit: u64 },
-    ExchangeMaxAlgoOrders { limit: u64 },
+    ExchangeMaxNumOrders {
+        limit: u64,
+    },
+    ExchangeMaxAlgoOrders {
+        limit: u64,
+    },
+    // Filters added by Binance since
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RateLimitMsg {
-    rate_limit_type: RateLimitType,
-    interval: Interval,
-    interval_num: u64,
-    limit: u64,
+    pub rate_limit_type: RateLimitType,
+    pub interval: Interval,
+    pub interval_num: u64,
+    pub limit: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -200,9 +631,27 @@ pub struct SymbolMsg {
     pub base_asset_precision: u64,
     pub quote_asset: String,
     pub quote_precision: u64,
+    #[serde(default)]
+    pub quote_asset_precision: u64,
+    #[serde(default)]
+    pub base_commission_precision: u64,
+    #[serde(default)]
+    pub quote_commission_precision: u64,
     pub order_types: Vec<OrderType>,
     pub iceberg_allowed: bool,
+    #[serde(default)]
+    pub oco_allowed: bool,
+    #[serde(default)]
+    pub quote_order_qty_market_allowed: bool,
+    #[serde(default)]
+    pub cancel_replace_allowed: bool,
+    #[serde(default)]
+    pub is_spot_trading_allowed: bool,
+    #[serde(default)]
+    pub is_margin_trading_allowed: bool,
     pub filters: Vec<SymbolFilter>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -232,6 +681,15 @@ pub enum SymbolFilter {
         apply_to_market: bool,
         avg_price_mins: u64,
     },
+    // Replaces MIN_NOTIONAL on most symbols
+    #[serde(rename_all = "camelCase")]
+    Notional {
+        min_notional: Decimal,
+        apply_min_to_market: bool,
+        max_notional: Decimal,
+        apply_max_to_market: bool,
+        avg_price_mins: u64,
+    },
     #[serde(rename_all = "camelCase")]
     IcebergParts { limit: u64 },
     #[serde(rename_all = "camelCase")]
@@ -246,6 +704,11 @@ pub enum SymbolFilter {
     MaxNumAlgoOrders { max_num_algo_orders: u64 },
     #[serde(rename_all = "camelCase")]
     MaxNumIcebergOrders { max_num_iceberg_orders: u64 },
+    #[serde(rename_all = "camelCase")]
+    MaxPosition { max_position: Decimal },
+    // Filters not checked by `OrderValidator`, e.g. TRAILING_DELTA
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -256,14 +719,14 @@ pub struct OrderBookMsg {
     pub asks: Vec<AskMsg>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderType {
     Market,
@@ -275,7 +738,7 @@ pub enum OrderType {
     LimitMaker,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderTimeInForce {
     Gtc,
@@ -283,6 +746,53 @@ pub enum OrderTimeInForce {
     Fok,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderResponseType {
+    Ack,
+    Result,
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CancelReplaceMode {
+    // Do not place the new order if the cancel fails
+    StopOnFailure,
+    // Place the new order even if the cancel fails
+    AllowFailure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CancelReplaceResult {
+    Success,
+    Failure,
+    NotAttempted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ContingencyType {
+    Oco,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ListStatusType {
+    Response,
+    ExecStarted,
+    AllDone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ListOrderStatus {
+    Executing,
+    AllDone,
+    Reject,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OrderExecType {
@@ -312,7 +822,7 @@ pub enum OrderRejectReason {
     None,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum RateLimitType {
     Orders,
@@ -320,10 +830,151 @@ pub enum RateLimitType {
     RawRequests,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Interval {
     Second,
     Minute,
+    Hour,
     Day,
 }
+
+impl Interval {
+    pub fn millis(self) -> i64 {
+        match self {
+            Interval::Second => 1000,
+            Interval::Minute => 60 * 1000,
+            Interval::Hour => 60 * 60 * 1000,
+            Interval::Day => 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::from_str;
+
+    use super::websocket::ListStatus;
+    use super::{
+        AvgPriceMsg, KlineInterval, KlineMsg, ListOrderStatus, ListStatusType, OrderListMsg,
+        OrderType, RollingPriceStatsMsg, WindowSize,
+    };
+    use error::Result;
+
+    #[test]
+    fn kline_interval() -> Result<()> {
+        // 2019-08-08T06:39:23.776Z, a thursday
+        let time = 1_565_246_363_776;
+        assert_eq!(KlineInterval::Minute15.open_time(time), 1_565_245_800_000);
+        assert_eq!(KlineInterval::Day1.close_time(time), 1_565_308_799_999);
+        // 2019-08-05, 2019-08-12
+        assert_eq!(KlineInterval::Week1.open_time(time), 1_564_963_200_000);
+        assert_eq!(KlineInterval::Week1.next_open_time(time), 1_565_568_000_000);
+        // 2019-08-01, 2019-09-01
+        assert_eq!(KlineInterval::Month1.open_time(time), 1_564_617_600_000);
+        assert_eq!(
+            KlineInterval::Month1.next_open_time(time),
+            1_567_296_000_000
+        );
+        // 2020-02-01 to 2020-03-01
+        assert_eq!(
+            KlineInterval::Month1.next_open_time(1_580_515_200_000),
+            1_583_020_800_000
+        );
+
+        assert_eq!("1M".parse::<KlineInterval>()?, KlineInterval::Month1);
+        assert!("2m".parse::<KlineInterval>().is_err());
+        assert_eq!(from_str::<KlineInterval>(r#""4h""#)?, KlineInterval::Hour4);
+        Ok(())
+    }
+
+    #[test]
+    fn kline() -> Result<()> {
+        let kline: KlineMsg = from_str(
+            r#"[1499040000000,"0.01634790","0.80000000","0.01575800","0.01577100",
+                "148976.11427815",1499644799999,"2434.19055334",308,"1756.87402397",
+                "28.46694368","0"]"#,
+        )?;
+        assert_eq!(kline.number_of_trades, 308);
+        assert_eq!(kline.close_time, 1_499_644_799_999);
+        assert!(from_str::<KlineMsg>(r#"[1499040000000,"0.01634790"]"#).is_err());
+        assert!(
+            from_str::<KlineMsg>(r#"[1499040000000,"x","0","0","0","0",0,"0",0,"0","0"]"#).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn order_list() -> Result<()> {
+        let list: OrderListMsg = from_str(
+            r#"{"orderListId":0,"contingencyType":"OCO","listStatusType":"EXEC_STARTED",
+                "listOrderStatus":"EXECUTING","listClientOrderId":"JYVpp3F0f5CAG15DhtrqLp",
+                "transactionTime":1563417480525,"symbol":"LTCBTC",
+                "orders":[{"symbol":"LTCBTC","orderId":2,"clientOrderId":"Kk7sqHb9J6mJWTMDVW7Vos"},
+                          {"symbol":"LTCBTC","orderId":3,"clientOrderId":"xTXKaGYd4bluPVp78IVRvl"}],
+                "orderReports":[
+                    {"symbol":"LTCBTC","orderId":2,"orderListId":0,"clientOrderId":"Kk7sqHb9J6mJWTMDVW7Vos",
+                     "transactTime":1563417480525,"price":"0.000000","origQty":"0.624363",
+                     "executedQty":"0.000000","cummulativeQuoteQty":"0.000000","status":"NEW",
+                     "timeInForce":"GTC","type":"STOP_LOSS","side":"BUY","stopPrice":"0.960664"},
+                    {"symbol":"LTCBTC","orderId":3,"orderListId":0,"clientOrderId":"xTXKaGYd4bluPVp78IVRvl",
+                     "transactTime":1563417480525,"price":"0.036435","origQty":"0.624363",
+                     "executedQty":"0.000000","cummulativeQuoteQty":"0.000000","status":"NEW",
+                     "timeInForce":"GTC","type":"LIMIT_MAKER","side":"BUY"}]}"#,
+        )?;
+        assert_eq!(list.list_status_type, ListStatusType::ExecStarted);
+        assert_eq!(list.orders.len(), 2);
+        assert_eq!(list.order_reports[1].order_type, OrderType::LimitMaker);
+        assert!(list.order_reports[1].stop_price.is_none());
+
+        // Queried order lists have no reports
+        let list: OrderListMsg = from_str(
+            r#"{"orderListId":27,"contingencyType":"OCO","listStatusType":"EXEC_STARTED",
+                "listOrderStatus":"EXECUTING","listClientOrderId":"h2USkA5YQpaXHPIrkd96xE",
+                "transactionTime":1565245656253,"symbol":"LTCBTC",
+                "orders":[{"symbol":"LTCBTC","orderId":4,"clientOrderId":"qD1gy3kc3Gx0rihm9Y3xwS"},
+                          {"symbol":"LTCBTC","orderId":5,"clientOrderId":"ARzZ9I00CPM8i3NhmU9Ega"}]}"#,
+        )?;
+        assert!(list.order_reports.is_empty());
+
+        let event: ListStatus = from_str(
+            r#"{"e":"listStatus","E":1564035303637,"s":"ETHBTC","g":2,"c":"OCO","l":"ALL_DONE",
+                "L":"ALL_DONE","r":"NONE","C":"F4QN4G8DlFATFlIUQ0cjdD","T":1564035303625,
+                "O":[{"s":"ETHBTC","i":17,"c":"AJYsMjErWJesZvqlJCTUgL"},
+                     {"s":"ETHBTC","i":18,"c":"bfYPSQdLoqAJeNrOr9adzq"}]}"#,
+        )?;
+        assert_eq!(event.list_order_status, ListOrderStatus::AllDone);
+        assert_eq!(event.orders[1].order_id, 18);
+        Ok(())
+    }
+
+    #[test]
+    fn rolling_price_stats() -> Result<()> {
+        let stats: RollingPriceStatsMsg = from_str(
+            r#"{"symbol":"BNBBTC","priceChange":"-8.00000000","priceChangePercent":"-88.889",
+                "weightedAvgPrice":"2.60427807","openPrice":"9.00000000","highPrice":"9.00000000",
+                "lowPrice":"1.00000000","lastPrice":"1.00000000","volume":"187.00000000",
+                "quoteVolume":"487.00000000","openTime":1641859200000,"closeTime":1642031999999,
+                "firstId":0,"lastId":60,"count":61}"#,
+        )?;
+        assert_eq!(stats.price_change, "-8".parse()?);
+        assert_eq!(stats.last_id, 60);
+
+        let avg_price: AvgPriceMsg = from_str(r#"{"mins":5,"price":"9.35751834"}"#)?;
+        assert_eq!(avg_price.mins, 5);
+        assert_eq!(avg_price.price, "9.35751834".parse()?);
+        Ok(())
+    }
+
+    #[test]
+    fn window_size() {
+        assert_eq!(WindowSize::Minutes(15).to_string(), "15m");
+        assert_eq!(WindowSize::Hours(4).to_string(), "4h");
+        assert_eq!(WindowSize::Days(7).to_string(), "7d");
+        assert!(WindowSize::Minutes(59).is_valid());
+        assert!(!WindowSize::Minutes(60).is_valid());
+        assert!(!WindowSize::Hours(0).is_valid());
+        assert!(WindowSize::Days(1).is_valid());
+        assert!(!WindowSize::Days(8).is_valid());
+    }
+}
@@ -0,0 +1,488 @@
+use serde::de::DeserializeOwned;
+
+use super::{
+    CancelReplaceMode, OrderAckMsg, OrderFullMsg, OrderResponseType, OrderResultMsg, OrderSide,
+    OrderTimeInForce, OrderType,
+};
+use decimal::Decimal;
+use error::{BinanceError, Result};
+
+// Response of a new order, selecting the newOrderRespType sent with it
+pub trait OrderResponse: DeserializeOwned {
+    const RESPONSE_TYPE: OrderResponseType;
+}
+
+impl OrderResponse for OrderAckMsg {
+    const RESPONSE_TYPE: OrderResponseType = OrderResponseType::Ack;
+}
+
+impl OrderResponse for OrderResultMsg {
+    const RESPONSE_TYPE: OrderResponseType = OrderResponseType::Result;
+}
+
+impl OrderResponse for OrderFullMsg {
+    const RESPONSE_TYPE: OrderResponseType = OrderResponseType::Full;
+}
+
+// Parameters of a new order
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/rest-api.md#new-order--trade
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewOrder {
+    pub(crate) symbol: String,
+    pub(crate) side: OrderSide,
+    #[serde(rename = "type")]
+    pub(crate) order_type: OrderType,
+    pub(crate) time_in_force: Option<OrderTimeInForce>,
+    pub(crate) quantity: Option<Decimal>,
+    pub(crate) quote_order_qty: Option<Decimal>,
+    pub(crate) price: Option<Decimal>,
+    pub(crate) new_client_order_id: Option<String>,
+    pub(crate) stop_price: Option<Decimal>,
+    pub(crate) iceberg_qty: Option<Decimal>,
+    pub(crate) new_order_resp_type: Option<OrderResponseType>,
+}
+
+impl NewOrder {
+    pub fn new(symbol: &str, side: OrderSide, order_type: OrderType) -> Self {
+        NewOrder {
+            symbol: symbol.into(),
+            side,
+            order_type,
+            time_in_force: None,
+            quantity: None,
+            quote_order_qty: None,
+            price: None,
+            new_client_order_id: None,
+            stop_price: None,
+            iceberg_qty: None,
+            new_order_resp_type: None,
+        }
+    }
+
+    // A GTC LIMIT order
+    pub fn limit(symbol: &str, side: OrderSide, qty: Decimal, price: Decimal) -> Self {
+        NewOrder::new(symbol, side, OrderType::Limit)
+            .time_in_force(OrderTimeInForce::Gtc)
+            .quantity(qty)
+            .price(price)
+    }
+
+    pub fn market(symbol: &str, side: OrderSide, qty: Decimal) -> Self {
+        NewOrder::new(symbol, side, OrderType::Market).quantity(qty)
+    }
+
+    pub fn time_in_force(mut self, time_in_force: OrderTimeInForce) -> Self {
+        self.time_in_force = Some(time_in_force);
+        self
+    }
+
+    pub fn quantity(mut self, qty: Decimal) -> Self {
+        self.quantity = Some(qty);
+        self
+    }
+
+    // MARKET orders only: spend (BUY) or receive (SELL) this amount of the quote asset
+    pub fn quote_order_qty(mut self, qty: Decimal) -> Self {
+        self.quote_order_qty = Some(qty);
+        self
+    }
+
+    pub fn price(mut self, price: Decimal) -> Self {
+        self.price = Some(price);
+        self
+    }
+
+    pub fn new_client_order_id(mut self, id: &str) -> Self {
+        self.new_client_order_id = Some(id.into());
+        self
+    }
+
+    pub fn stop_price(mut self, stop_price: Decimal) -> Self {
+        self.stop_price = Some(stop_price);
+        self
+    }
+
+    pub fn iceberg_qty(mut self, qty: Decimal) -> Self {
+        self.iceberg_qty = Some(qty);
+        self
+    }
+
+    // Checks that the mandatory parameters of the order type are present
+    // and that no parameter the order type does not accept is set.
+    pub fn validate(&self) -> Result<()> {
+        // (price, stop price, time in force) requirements per order type
+        let (price, stop_price, time_in_force) = match self.order_type {
+            OrderType::Limit => (true, false, true),
+            OrderType::Market => (false, false, false),
+            OrderType::StopLoss => (false, true, false),
+            OrderType::StopLossLimit => (true, true, true),
+            OrderType::TakeProfit => (false, true, false),
+            OrderType::TakeProfitLimit => (true, true, true),
+            OrderType::LimitMaker => (true, false, false),
+        };
+
+        check_field(self.order_type, "price", price, self.price.is_some())?;
+        check_field(
+            self.order_type,
+            "stopPrice",
+            stop_price,
+            self.stop_price.is_some(),
+        )?;
+        check_field(
+            self.order_type,
+            "timeInForce",
+            time_in_force,
+            self.time_in_force.is_some(),
+        )?;
+
+        match (self.quantity, self.quote_order_qty) {
+            (Some(_), Some(_)) => invalid("only one of quantity and quoteOrderQty can be set")?,
+            (None, Some(_)) if self.order_type != OrderType::Market => {
+                invalid("quoteOrderQty is only allowed for MARKET orders")?
+            }
+            (None, None) if self.order_type == OrderType::Market => {
+                invalid("MARKET orders require quantity or quoteOrderQty")?
+            }
+            (None, None) => invalid(&format!("{:?} orders require quantity", self.order_type))?,
+            _ => {}
+        }
+
+        if self.iceberg_qty.is_some() {
+            if !price {
+                invalid(&format!(
+                    "icebergQty is not allowed for {:?} orders",
+                    self.order_type
+                ))?
+            }
+            if time_in_force && self.time_in_force != Some(OrderTimeInForce::Gtc) {
+                invalid("iceberg orders require GTC time in force")?
+            }
+        }
+
+        let amounts = [
+            ("quantity", self.quantity),
+            ("quoteOrderQty", self.quote_order_qty),
+            ("price", self.price),
+            ("stopPrice", self.stop_price),
+            ("icebergQty", self.iceberg_qty),
+        ];
+        for (name, amount) in amounts.iter() {
+            if let Some(amount) = amount {
+                if *amount <= Decimal::from(0) {
+                    invalid(&format!("{} must be positive", name))?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Cancels an order and places `order` in a single request
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/rest-api.md#cancel-an-existing-order-and-send-a-new-order-trade
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelReplaceOrder {
+    #[serde(flatten)]
+    pub(crate) order: NewOrder,
+    pub(crate) cancel_replace_mode: CancelReplaceMode,
+    pub(crate) cancel_order_id: Option<i64>,
+    pub(crate) cancel_orig_client_order_id: Option<String>,
+    pub(crate) cancel_new_client_order_id: Option<String>,
+}
+
+impl CancelReplaceOrder {
+    pub fn by_order_id(order: NewOrder, mode: CancelReplaceMode, order_id: i64) -> Self {
+        CancelReplaceOrder {
+            order,
+            cancel_replace_mode: mode,
+            cancel_order_id: Some(order_id),
+            cancel_orig_client_order_id: None,
+            cancel_new_client_order_id: None,
+        }
+    }
+
+    pub fn by_client_order_id(
+        order: NewOrder,
+        mode: CancelReplaceMode,
+        orig_client_order_id: &str,
+    ) -> Self {
+        CancelReplaceOrder {
+            order,
+            cancel_replace_mode: mode,
+            cancel_order_id: None,
+            cancel_orig_client_order_id: Some(orig_client_order_id.into()),
+            cancel_new_client_order_id: None,
+        }
+    }
+
+    // Client id of the cancel itself
+    pub fn cancel_new_client_order_id(mut self, id: &str) -> Self {
+        self.cancel_new_client_order_id = Some(id.into());
+        self
+    }
+}
+
+// Parameters of a new OCO order list: a LIMIT_MAKER order at `price` and a
+// STOP_LOSS (or STOP_LOSS_LIMIT with a stop limit price) order triggered at `stop_price`
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/rest-api.md#new-oco-trade
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewOcoOrder {
+    pub(crate) symbol: String,
+    pub(crate) list_client_order_id: Option<String>,
+    pub(crate) side: OrderSide,
+    pub(crate) quantity: Decimal,
+    pub(crate) limit_client_order_id: Option<String>,
+    pub(crate) price: Decimal,
+    pub(crate) limit_iceberg_qty: Option<Decimal>,
+    pub(crate) stop_client_order_id: Option<String>,
+    pub(crate) stop_price: Decimal,
+    pub(crate) stop_limit_price: Option<Decimal>,
+    pub(crate) stop_iceberg_qty: Option<Decimal>,
+    pub(crate) stop_limit_time_in_force: Option<OrderTimeInForce>,
+    pub(crate) new_order_resp_type: Option<OrderResponseType>,
+}
+
+impl NewOcoOrder {
+    pub fn new(
+        symbol: &str,
+        side: OrderSide,
+        qty: Decimal,
+        price: Decimal,
+        stop_price: Decimal,
+    ) -> Self {
+        NewOcoOrder {
+            symbol: symbol.into(),
+            list_client_order_id: None,
+            side,
+            quantity: qty,
+            limit_client_order_id: None,
+            price,
+            limit_iceberg_qty: None,
+            stop_client_order_id: None,
+            stop_price,
+            stop_limit_price: None,
+            stop_iceberg_qty: None,
+            stop_limit_time_in_force: None,
+            new_order_resp_type: None,
+        }
+    }
+
+    pub fn list_client_order_id(mut self, id: &str) -> Self {
+        self.list_client_order_id = Some(id.into());
+        self
+    }
+
+    pub fn limit_client_order_id(mut self, id: &str) -> Self {
+        self.limit_client_order_id = Some(id.into());
+        self
+    }
+
+    pub fn limit_iceberg_qty(mut self, qty: Decimal) -> Self {
+        self.limit_iceberg_qty = Some(qty);
+        self
+    }
+
+    pub fn stop_client_order_id(mut self, id: &str) -> Self {
+        self.stop_client_order_id = Some(id.into());
+        self
+    }
+
+    // Makes the stop order a STOP_LOSS_LIMIT order at this price
+    pub fn stop_limit_price(mut self, price: Decimal, time_in_force: OrderTimeInForce) -> Self {
+        self.stop_limit_price = Some(price);
+        self.stop_limit_time_in_force = Some(time_in_force);
+        self
+    }
+
+    pub fn stop_iceberg_qty(mut self, qty: Decimal) -> Self {
+        self.stop_iceberg_qty = Some(qty);
+        self
+    }
+
+    // Checks the amounts and that the limit order is on the other side of the stop price
+    pub fn validate(&self) -> Result<()> {
+        let amounts = [
+            ("quantity", Some(self.quantity)),
+            ("price", Some(self.price)),
+            ("stopPrice", Some(self.stop_price)),
+            ("stopLimitPrice", self.stop_limit_price),
+            ("limitIcebergQty", self.limit_iceberg_qty),
+            ("stopIcebergQty", self.stop_iceberg_qty),
+        ];
+        for (name, amount) in amounts.iter() {
+            if let Some(amount) = amount {
+                if *amount <= Decimal::from(0) {
+                    invalid(&format!("{} must be positive", name))?
+                }
+            }
+        }
+
+        match self.side {
+            OrderSide::Sell if self.price <= self.stop_price => {
+                invalid("SELL OCO orders require price above stopPrice")?
+            }
+            OrderSide::Buy if self.price >= self.stop_price => {
+                invalid("BUY OCO orders require price below stopPrice")?
+            }
+            _ => {}
+        }
+
+        if self.stop_iceberg_qty.is_some()
+            && self.stop_limit_time_in_force != Some(OrderTimeInForce::Gtc)
+        {
+            invalid("stopIcebergQty requires a GTC stop limit order")?
+        }
+        Ok(())
+    }
+
+    // The LIMIT_MAKER and STOP_LOSS(_LIMIT) orders placed by the list
+    pub(crate) fn legs(&self) -> (NewOrder, NewOrder) {
+        let mut limit = NewOrder::new(&self.symbol, self.side, OrderType::LimitMaker)
+            .quantity(self.quantity)
+            .price(self.price);
+        limit.iceberg_qty = self.limit_iceberg_qty;
+
+        let mut stop = match self.stop_limit_price {
+            Some(price) => {
+                NewOrder::new(&self.symbol, self.side, OrderType::StopLossLimit).price(price)
+            }
+            None => NewOrder::new(&self.symbol, self.side, OrderType::StopLoss),
+        }
+        .quantity(self.quantity)
+        .stop_price(self.stop_price);
+        stop.time_in_force = self.stop_limit_time_in_force;
+        stop.iceberg_qty = self.stop_iceberg_qty;
+        (limit, stop)
+    }
+}
+
+fn check_field(order_type: OrderType, name: &str, required: bool, present: bool) -> Result<()> {
+    match (required, present) {
+        (true, false) => invalid(&format!("{:?} orders require {}", order_type, name)),
+        (false, true) => invalid(&format!(
+            "{} is not allowed for {:?} orders",
+            name, order_type
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn invalid(reason: &str) -> Result<()> {
+    Err(BinanceError::InvalidOrder(reason.into()))?
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{from_str, to_value};
+
+    use super::{CancelReplaceOrder, NewOcoOrder, NewOrder};
+    use error::{BinanceResponse, Result};
+    use model::{CancelReplaceMode, CancelReplaceMsg, CancelReplaceResult, OrderAckMsg};
+    use model::{OrderSide, OrderTimeInForce, OrderType};
+
+    #[test]
+    fn validate_required_fields() -> Result<()> {
+        NewOrder::limit("BNBBTC", OrderSide::Buy, 1.into(), "0.1".parse()?).validate()?;
+        NewOrder::market("BNBBTC", OrderSide::Sell, 1.into()).validate()?;
+        NewOrder::new("BNBBTC", OrderSide::Sell, OrderType::StopLossLimit)
+            .quantity(1.into())
+            .price("0.1".parse()?)
+            .stop_price("0.11".parse()?)
+            .time_in_force(OrderTimeInForce::Gtc)
+            .validate()?;
+
+        assert!(
+            NewOrder::new("BNBBTC", OrderSide::Sell, OrderType::StopLoss)
+                .quantity(1.into())
+                .validate()
+                .is_err()
+        );
+        assert!(
+            NewOrder::new("BNBBTC", OrderSide::Buy, OrderType::LimitMaker)
+                .quantity(1.into())
+                .price("0.1".parse()?)
+                .time_in_force(OrderTimeInForce::Gtc)
+                .validate()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_quantities() -> Result<()> {
+        NewOrder::new("BNBBTC", OrderSide::Buy, OrderType::Market)
+            .quote_order_qty(10.into())
+            .validate()?;
+
+        assert!(NewOrder::market("BNBBTC", OrderSide::Buy, 1.into())
+            .quote_order_qty(10.into())
+            .validate()
+            .is_err());
+        assert!(
+            NewOrder::limit("BNBBTC", OrderSide::Buy, 0.into(), "0.1".parse()?)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            NewOrder::limit("BNBBTC", OrderSide::Buy, 10.into(), "0.1".parse()?)
+                .time_in_force(OrderTimeInForce::Ioc)
+                .iceberg_qty(2.into())
+                .validate()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn validate_oco() -> Result<()> {
+        NewOcoOrder::new(
+            "BNBBTC",
+            OrderSide::Sell,
+            1.into(),
+            "0.12".parse()?,
+            "0.09".parse()?,
+        )
+        .stop_limit_price("0.089".parse()?, OrderTimeInForce::Gtc)
+        .validate()?;
+
+        assert!(NewOcoOrder::new(
+            "BNBBTC",
+            OrderSide::Buy,
+            1.into(),
+            "0.12".parse()?,
+            "0.09".parse()?
+        )
+        .validate()
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_replace() -> Result<()> {
+        let request = CancelReplaceOrder::by_order_id(
+            NewOrder::limit("BNBBTC", OrderSide::Buy, 1.into(), "0.1".parse()?),
+            CancelReplaceMode::StopOnFailure,
+            12,
+        );
+        let params = to_value(&request)?;
+        assert_eq!(params["symbol"], "BNBBTC");
+        assert_eq!(params["cancelReplaceMode"], "STOP_ON_FAILURE");
+        assert_eq!(params["cancelOrderId"], 12);
+
+        let replaced: CancelReplaceMsg<OrderAckMsg> = from_str(
+            r#"{"cancelResult":"FAILURE","newOrderResult":"NOT_ATTEMPTED",
+                "cancelResponse":{"code":-2011,"msg":"Unknown order sent."},
+                "newOrderResponse":null}"#,
+        )?;
+        assert_eq!(replaced.cancel_result, CancelReplaceResult::Failure);
+        match replaced.cancel_response {
+            Some(BinanceResponse::Error(e)) => assert_eq!(e.code, -2011),
+            r => panic!("unexpected response {:?}", r),
+        }
+        assert!(replaced.new_order_response.is_none());
+        Ok(())
+    }
+}
@@ -1,21 +1,21 @@
 use super::{
-    AskMsg, BidMsg, OrderBookMsg, OrderExecType, OrderRejectReason, OrderSide, OrderStatus,
-    OrderTimeInForce, OrderType,
+    AskMsg, BidMsg, ContingencyType, KlineInterval, ListOrderStatus, ListStatusType, OrderBookMsg,
+    OrderExecType, OrderRejectReason, OrderSide, OrderStatus, OrderTimeInForce, OrderType,
 };
 use decimal::Decimal;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub enum BinanceSubscription {
     // Websocket streams
-    AggregateTrade(String),      //symbol
-    Trade(String),               //symbol
-    Candlestick(String, String), //symbol, interval
-    MiniTicker(String),          //symbol
+    AggregateTrade(String),             //symbol
+    Trade(String),                      //symbol
+    Candlestick(String, KlineInterval), //symbol, interval
+    MiniTicker(String),                 //symbol
     MiniTickerAll,
     Ticker(String), // symbol
     TickerAll,
-    OrderBook(String, u64),    //symbol, depth
-    DiffDepth(String),         //symbol
+    OrderBook(String, u64), //symbol, depth
+    DiffDepth(String),      //symbol
 
     // User data streams
     UserData(String), // listen key
@@ -36,9 +36,15 @@ pub enum BinanceWebsocketMessage {
     Ping,
     Pong,
 
+    // Connection control events of a reconnecting websocket
+    Disconnected(Vec<BinanceSubscription>),
+    Reconnected(Vec<BinanceSubscription>),
+
     // User data streams
     AccountUpdate(AccountUpdate),
     OrderUpdate(OrderUpdate),
+    ListStatus(ListStatus),
+    ListenKeyExpired(ListenKeyExpired),
 
     Binary(Vec<u8>), // Unexpected, unparsed
 }
@@ -47,14 +53,15 @@ pub enum BinanceWebsocketMessage {
 // https://github.com/binance-exchange/binance-official-api-docs/blob/master/web-socket-streams.md
 
 // https://github.com/binance-exchange/binance-official-api-docs/blob/master/web-socket-streams.md#aggregate-trade-streams
+// Also returned by `Binance::get_agg_trades`, the REST fields missing from the stream being filled in
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AggregateTrade {
-    #[serde(rename = "e")]
+    #[serde(rename = "e", default)]
     pub event_type: String,
-    #[serde(rename = "E")]
+    #[serde(rename = "E", default)]
     pub event_time: u64,
-    #[serde(rename = "s")]
+    #[serde(rename = "s", default)]
     pub symbol: String,
     #[serde(rename = "a")]
     pub aggregate_trade_id: i64,
@@ -75,30 +82,32 @@ pub struct AggregateTrade {
 }
 
 // https://github.com/binance-exchange/binance-official-api-docs/blob/master/web-socket-streams.md#trade-streams
+// Also returned by `Binance::get_trades` and `get_historical_trades`, which use the long field names
+// and have no order ids
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Trade {
-    #[serde(rename = "e")]
+    #[serde(rename = "e", default)]
     pub event_type: String,
-    #[serde(rename = "E")]
+    #[serde(rename = "E", default)]
     pub event_time: u64,
-    #[serde(rename = "s")]
+    #[serde(rename = "s", default)]
     pub symbol: String,
-    #[serde(rename = "t")]
+    #[serde(rename = "t", alias = "id")]
     pub trade_id: i64,
-    #[serde(rename = "p")]
+    #[serde(rename = "p", alias = "price")]
     pub price: Decimal,
-    #[serde(rename = "q")]
+    #[serde(rename = "q", alias = "qty")]
     pub qty: Decimal,
-    #[serde(rename = "b")]
-    pub buyer_order_id: i64,
-    #[serde(rename = "a")]
-    pub seller_order_id: i64,
-    #[serde(rename = "T")]
+    #[serde(rename = "b", default)]
+    pub buyer_order_id: Option<i64>,
+    #[serde(rename = "a", default)]
+    pub seller_order_id: Option<i64>,
+    #[serde(rename = "T", alias = "time")]
     pub trade_time: u64,
-    #[serde(rename = "m")]
+    #[serde(rename = "m", alias = "isBuyerMaker")]
     pub is_buyer_maker: bool,
-    #[serde(rename = "M", skip_serializing)]
+    #[serde(rename = "M", alias = "isBestMatch", skip_serializing)]
     pub m_ignore: bool,
 }
 
@@ -127,7 +136,7 @@ pub struct CandlestickMessageKline {
     #[serde(rename = "s")]
     pub symbol: String,
     #[serde(rename = "i")]
-    pub interval: String,
+    pub interval: KlineInterval,
     #[serde(rename = "f")]
     pub first_trade_id: i64,
     #[serde(rename = "L")]
@@ -263,22 +272,9 @@ pub struct AccountUpdate {
     pub event_type: String,
     #[serde(rename = "E")]
     pub event_time: u64,
-    #[serde(rename = "m")]
-    pub maker_commision_rate: u64,
-    #[serde(rename = "t")]
-    pub taker_commision_rate: u64,
-    #[serde(rename = "b")]
-    pub buyer_commision_rate: u64,
-    #[serde(rename = "s")]
-    pub seller_commision_rate: u64,
-    #[serde(rename = "T")]
-    pub can_trade: bool,
-    #[serde(rename = "W")]
-    pub can_withdraw: bool,
-    #[serde(rename = "D")]
-    pub can_deposit: bool,
     #[serde(rename = "u")]
     pub last_update_time: u64,
+    // Balances of the assets changed by the event
     #[serde(rename = "B")]
     pub balances: Vec<AccountUpdateBalance>,
 }
@@ -362,3 +358,51 @@ pub struct OrderUpdate {
     #[serde(rename = "Y")]
     pub last_quote_transacted_qty: Decimal,
 }
+
+// Update of an order list
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/user-data-stream.md#order-update
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListStatus {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "g")]
+    pub order_list_id: i64,
+    #[serde(rename = "c")]
+    pub contingency_type: ContingencyType,
+    #[serde(rename = "l")]
+    pub list_status_type: ListStatusType,
+    #[serde(rename = "L")]
+    pub list_order_status: ListOrderStatus,
+    #[serde(rename = "r")]
+    pub list_reject_reason: String,
+    #[serde(rename = "C")]
+    pub list_client_order_id: String,
+    #[serde(rename = "T")]
+    pub transaction_time: u64,
+    #[serde(rename = "O")]
+    pub orders: Vec<ListStatusOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListStatusOrder {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "i")]
+    pub order_id: i64,
+    #[serde(rename = "c")]
+    pub client_order_id: String,
+}
+
+// https://github.com/binance-exchange/binance-official-api-docs/blob/master/user-data-stream.md#listen-key-expired
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListenKeyExpired {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+}
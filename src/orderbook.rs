@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use failure::Error;
+use futures::{try_ready, Async, Future, Poll, Stream};
+
+use client::Binance;
+use decimal::Decimal;
+use error::Result;
+use model::websocket::{BinanceWebsocketMessage, DiffDepth};
+use model::{OrderBookMsg, OrderSide};
+
+static SNAPSHOT_LIMIT: u64 = 1000;
+
+type Snapshot = Box<dyn Future<Item = OrderBookMsg, Error = Error> + Send>;
+
+// Price ladders of one symbol, as maintained from a REST snapshot and diff depth events
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    pub last_update_id: i64,
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+}
+
+impl OrderBook {
+    pub fn from_snapshot(snapshot: &OrderBookMsg) -> Self {
+        OrderBook {
+            last_update_id: snapshot.last_update_id,
+            bids: snapshot.bids.iter().map(|b| (b.price, b.qty)).collect(),
+            asks: snapshot.asks.iter().map(|a| (a.price, a.qty)).collect(),
+        }
+    }
+
+    // Applies a diff depth event if it continues this book.
+    // Returns `false` when the event is not the next in sequence and the book must be resynced.
+    // Events already contained in the book are ignored.
+    pub fn apply(&mut self, diff: &DiffDepth) -> bool {
+        if diff.final_update_id <= self.last_update_id {
+            return true;
+        }
+        if diff.first_update_id > self.last_update_id + 1 {
+            return false;
+        }
+
+        for bid in &diff.bids {
+            update_level(&mut self.bids, bid.price, bid.qty);
+        }
+        for ask in &diff.asks {
+            update_level(&mut self.asks, ask.price, ask.qty);
+        }
+        self.last_update_id = diff.final_update_id;
+        true
+    }
+
+    // Highest bid as (price, qty)
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        self.bids.iter().next_back().map(|(p, q)| (*p, *q))
+    }
+
+    // Lowest ask as (price, qty)
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        self.asks.iter().next().map(|(p, q)| (*p, *q))
+    }
+
+    // Top `n` bid levels, best first
+    pub fn bids(&self, n: usize) -> Vec<(Decimal, Decimal)> {
+        self.bids
+            .iter()
+            .rev()
+            .take(n)
+            .map(|(p, q)| (*p, *q))
+            .collect()
+    }
+
+    // Top `n` ask levels, best first
+    pub fn asks(&self, n: usize) -> Vec<(Decimal, Decimal)> {
+        self.asks.iter().take(n).map(|(p, q)| (*p, *q)).collect()
+    }
+
+    // Average price of a market order of `qty` on `side` sweeping the book.
+    // Returns `None` if the book is not deep enough.
+    pub fn vwap(&self, side: OrderSide, qty: Decimal) -> Option<Decimal> {
+        let zero = Decimal::from(0);
+        if qty <= zero {
+            return None;
+        }
+
+        let levels: Box<dyn Iterator<Item = (&Decimal, &Decimal)>> = match side {
+            OrderSide::Buy => Box::new(self.asks.iter()),
+            OrderSide::Sell => Box::new(self.bids.iter().rev()),
+        };
+
+        let mut remaining = qty;
+        let mut notional = zero;
+        for (price, level_qty) in levels {
+            let fill = if *level_qty < remaining {
+                *level_qty
+            } else {
+                remaining
+            };
+            notional += fill * *price;
+            remaining -= fill;
+            if remaining == zero {
+                return Some(notional / qty);
+            }
+        }
+        None
+    }
+}
+
+fn update_level(levels: &mut BTreeMap<Decimal, Decimal>, price: Decimal, qty: Decimal) {
+    if qty == Decimal::from(0) {
+        levels.remove(&price);
+    } else {
+        levels.insert(price, qty);
+    }
+}
+
+// Keeps an `OrderBook` in sync from a websocket stream subscribed to `DiffDepth` of the symbol,
+// following https://github.com/binance-exchange/binance-official-api-docs/blob/master/web-socket-streams.md#how-to-manage-a-local-order-book-correctly
+// A fresh snapshot is fetched whenever a gap in the update ids is detected.
+// Yields the whole book after every applied update.
+pub struct LocalOrderBook<S> {
+    fetch: Box<dyn Fn() -> Result<Snapshot> + Send>,
+    symbol: String,
+    stream: S,
+    buffer: VecDeque<DiffDepth>,
+    snapshot: Option<Snapshot>,
+    book: Option<OrderBook>,
+}
+
+impl<S> LocalOrderBook<S>
+where
+    S: Stream<Item = BinanceWebsocketMessage, Error = Error>,
+{
+    pub fn new(binance: &Binance, symbol: &str, stream: S) -> Self {
+        let binance = binance.clone();
+        let symbol = symbol.to_uppercase();
+        let depth_symbol = symbol.clone();
+        Self::with_fetch(symbol, stream, move || {
+            Ok(Box::new(binance.get_depth(&depth_symbol, SNAPSHOT_LIMIT)?))
+        })
+    }
+
+    fn with_fetch<F>(symbol: String, stream: S, fetch: F) -> Self
+    where
+        F: Fn() -> Result<Snapshot> + Send + 'static,
+    {
+        LocalOrderBook {
+            fetch: Box::new(fetch),
+            symbol,
+            stream,
+            buffer: VecDeque::new(),
+            snapshot: None,
+            book: None,
+        }
+    }
+
+    // The current book, `None` while (re)syncing
+    pub fn book(&self) -> Option<&OrderBook> {
+        self.book.as_ref()
+    }
+
+    fn request_snapshot(&mut self) -> Result<()> {
+        trace!("[OrderBook] Fetching snapshot of {}", self.symbol);
+        self.snapshot = Some((self.fetch)()?);
+        Ok(())
+    }
+
+    // Replays buffered events on top of the snapshot.
+    // Returns `false` if the snapshot is older than the buffered events and another one was requested.
+    fn sync(&mut self, snapshot: &OrderBookMsg) -> Result<bool> {
+        let mut book = OrderBook::from_snapshot(snapshot);
+        while let Some(diff) = self.buffer.pop_front() {
+            if !book.apply(&diff) {
+                self.buffer.push_front(diff);
+                self.request_snapshot()?;
+                return Ok(false);
+            }
+        }
+        self.book = Some(book);
+        Ok(true)
+    }
+}
+
+impl<S> Stream for LocalOrderBook<S>
+where
+    S: Stream<Item = BinanceWebsocketMessage, Error = Error>,
+{
+    type Item = OrderBook;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(mut snapshot) = self.snapshot.take() {
+                match snapshot.poll()? {
+                    Async::Ready(snapshot) => {
+                        if self.sync(&snapshot)? {
+                            return Ok(Async::Ready(self.book.clone()));
+                        }
+                        continue;
+                    }
+                    Async::NotReady => self.snapshot = Some(snapshot),
+                }
+            }
+
+            let diff = match try_ready!(self.stream.poll()) {
+                Some(BinanceWebsocketMessage::DiffDepth(diff)) => diff,
+                Some(_) => continue,
+                None => return Ok(Async::Ready(None)),
+            };
+            if diff.symbol != self.symbol {
+                continue;
+            }
+
+            match self.book.take() {
+                Some(mut book) => {
+                    if book.apply(&diff) {
+                        self.book = Some(book);
+                        return Ok(Async::Ready(self.book.clone()));
+                    }
+                    warn!(
+                        "[OrderBook] Gap in {} updates: expected {}, got {}",
+                        self.symbol,
+                        book.last_update_id + 1,
+                        diff.first_update_id
+                    );
+                    self.buffer.push_back(diff);
+                    self.request_snapshot()?;
+                }
+                None => {
+                    self.buffer.push_back(diff);
+                    if self.snapshot.is_none() {
+                        self.request_snapshot()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use failure::Error;
+    use futures::future::ok;
+    use futures::stream::iter_ok;
+    use futures::{Future, Stream};
+    use serde_json::from_str;
+
+    use super::{LocalOrderBook, OrderBook};
+    use error::Result;
+    use model::websocket::{BinanceWebsocketMessage, DiffDepth};
+    use model::{OrderBookMsg, OrderSide};
+
+    fn diff(first: i64, last: i64, bids: &str, asks: &str) -> Result<DiffDepth> {
+        Ok(from_str(&format!(
+            r#"{{"e":"depthUpdate","E":1,"s":"BNBBTC","U":{},"u":{},"b":{},"a":{}}}"#,
+            first, last, bids, asks
+        ))?)
+    }
+
+    fn book() -> Result<OrderBook> {
+        let snapshot: OrderBookMsg = from_str(
+            r#"{"lastUpdateId":10,
+                "bids":[["0.9","1",[]],["0.8","2",[]]],
+                "asks":[["1.1","1",[]],["1.2","3",[]]]}"#,
+        )?;
+        Ok(OrderBook::from_snapshot(&snapshot))
+    }
+
+    #[test]
+    fn apply_in_sequence() -> Result<()> {
+        let mut book = book()?;
+
+        assert!(book.apply(&diff(5, 10, r#"[["0.9","0"]]"#, "[]")?));
+        assert_eq!(book.best_bid(), Some(("0.9".parse()?, 1.into())));
+
+        assert!(book.apply(&diff(9, 12, r#"[["0.9","0"]]"#, r#"[["1.0","5"]]"#)?));
+        assert_eq!(book.last_update_id, 12);
+        assert_eq!(book.best_bid(), Some(("0.8".parse()?, 2.into())));
+        assert_eq!(book.best_ask(), Some(("1.0".parse()?, 5.into())));
+        assert_eq!(
+            book.asks(2),
+            vec![("1.0".parse()?, 5.into()), ("1.1".parse()?, 1.into())]
+        );
+
+        assert!(!book.apply(&diff(14, 15, "[]", "[]")?));
+        assert_eq!(book.last_update_id, 12);
+        Ok(())
+    }
+
+    #[test]
+    fn vwap() -> Result<()> {
+        let book = book()?;
+
+        assert_eq!(book.vwap(OrderSide::Buy, 1.into()), Some("1.1".parse()?));
+        assert_eq!(book.vwap(OrderSide::Buy, 2.into()), Some("1.15".parse()?));
+        assert_eq!(
+            book.vwap(OrderSide::Sell, 3.into()),
+            Some("0.8333333333333333333333333333".parse()?)
+        );
+        assert_eq!(book.vwap(OrderSide::Sell, 4.into()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn resync_after_gap() -> Result<()> {
+        let events = vec![
+            diff(11, 12, "[]", "[]")?,
+            diff(15, 16, "[]", "[]")?,
+            diff(21, 22, "[]", "[]")?,
+        ];
+        let stream =
+            iter_ok::<_, Error>(events.into_iter().map(BinanceWebsocketMessage::DiffDepth));
+
+        // Snapshots at update 10, then 20
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let count = fetched.clone();
+        let books = LocalOrderBook::with_fetch("BNBBTC".into(), stream, move || {
+            let n = count.fetch_add(1, Ordering::SeqCst);
+            let snapshot: OrderBookMsg = from_str(&format!(
+                r#"{{"lastUpdateId":{},"bids":[],"asks":[]}}"#,
+                10 * (n + 1)
+            ))?;
+            Ok(Box::new(ok(snapshot)))
+        });
+
+        // Can be spawned on the runtime
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&books);
+
+        let ids: Vec<_> = books
+            .collect()
+            .wait()?
+            .into_iter()
+            .map(|book| book.last_update_id)
+            .collect();
+        assert_eq!(ids, vec![12, 20, 22]);
+        assert_eq!(fetched.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
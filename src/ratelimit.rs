@@ -0,0 +1,389 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use failure::Error;
+use futures::future::{err, loop_fn, ok, Either, Loop};
+use futures::Future;
+use hyper::{HeaderMap, Method, StatusCode};
+use tokio::timer::Delay;
+
+use error::BinanceError;
+use model::{Interval, RateLimitMsg, RateLimitType};
+
+// What to do with a request that would exceed a rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitPolicy {
+    // Hold the request until the limit window resets
+    #[default]
+    Wait,
+    // Fail the request with `BinanceError::RateLimited`
+    Reject,
+}
+
+#[derive(Debug, Clone)]
+struct Limit {
+    limit_type: RateLimitType,
+    interval_ms: i64,
+    limit: u64,
+    // index of the current window since the epoch, by the local clock; the X-MBX-USED-WEIGHT
+    // headers resync the count when the local and server windows disagree
+    window: i64,
+    used: u64,
+}
+
+impl Limit {
+    fn new(limit_type: RateLimitType, interval: Interval, interval_num: u64, limit: u64) -> Self {
+        Limit {
+            limit_type,
+            interval_ms: interval.millis() * interval_num as i64,
+            limit,
+            window: 0,
+            used: 0,
+        }
+    }
+
+    fn roll(&mut self, now: i64) {
+        let window = now / self.interval_ms;
+        if window != self.window {
+            self.window = window;
+            self.used = 0;
+        }
+    }
+
+    fn cost(&self, weight: u64, orders: u64) -> u64 {
+        match self.limit_type {
+            RateLimitType::RequestWeight => weight,
+            RateLimitType::Orders => orders,
+            RateLimitType::RawRequests => 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limits: Vec<Limit>,
+    // set after a 429 or 418 response, in ms since the epoch
+    banned_until: Option<i64>,
+}
+
+// Client side request weight and order count limiter shared by all clones of a `Binance`.
+// Starts with the documented default limits until seeded from the exchange info,
+// and is corrected from the X-MBX-USED-WEIGHT-* and X-MBX-ORDER-COUNT-* response headers.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        let limits = vec![
+            Limit::new(RateLimitType::RequestWeight, Interval::Minute, 1, 1200),
+            Limit::new(RateLimitType::Orders, Interval::Second, 10, 100),
+            Limit::new(RateLimitType::Orders, Interval::Day, 1, 200_000),
+            Limit::new(RateLimitType::RawRequests, Interval::Minute, 5, 6100),
+        ];
+        RateLimiter {
+            policy,
+            state: Arc::new(Mutex::new(State {
+                limits,
+                banned_until: None,
+            })),
+        }
+    }
+
+    pub fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    // Replaces the limits with the ones announced in the exchange info,
+    // keeping the usage of windows that did not change.
+    pub fn seed(&self, rate_limits: &[RateLimitMsg]) {
+        let mut state = self.state.lock().unwrap();
+        let limits = rate_limits
+            .iter()
+            .map(|r| {
+                let mut limit = Limit::new(r.rate_limit_type, r.interval, r.interval_num, r.limit);
+                if let Some(old) = state.limits.iter().find(|l| {
+                    l.limit_type == limit.limit_type && l.interval_ms == limit.interval_ms
+                }) {
+                    limit.window = old.window;
+                    limit.used = old.used;
+                }
+                limit
+            })
+            .collect();
+        state.limits = limits;
+    }
+
+    // Used amount and limit of the current window of a limit, if it is tracked
+    pub fn usage(
+        &self,
+        limit_type: RateLimitType,
+        interval: Interval,
+        interval_num: u64,
+    ) -> Option<(u64, u64)> {
+        let now = Utc::now().timestamp_millis();
+        let interval_ms = interval.millis() * interval_num as i64;
+        let mut state = self.state.lock().unwrap();
+        state
+            .limits
+            .iter_mut()
+            .find(|l| l.limit_type == limit_type && l.interval_ms == interval_ms)
+            .map(|l| {
+                l.roll(now);
+                (l.used, l.limit)
+            })
+    }
+
+    // Resolves once the request fits in every limit, reserving its weight and orders.
+    // With `RateLimitPolicy::Reject` it fails instead of waiting.
+    pub fn acquire(&self, weight: u64, orders: u64) -> impl Future<Item = (), Error = Error> {
+        let limiter = self.clone();
+        loop_fn((), move |()| {
+            let now = Utc::now().timestamp_millis();
+            match limiter.try_acquire(weight, orders, now) {
+                Ok(()) => Either::A(ok(Loop::Break(()))),
+                Err(wait) => match limiter.policy {
+                    RateLimitPolicy::Reject => Either::A(err(BinanceError::RateLimited {
+                        retry_after: wait as u64,
+                    }
+                    .into())),
+                    RateLimitPolicy::Wait => {
+                        debug!("[RateLimiter] Waiting {} ms for rate limit", wait);
+                        let deadline = Instant::now() + Duration::from_millis(wait as u64);
+                        Either::B(Delay::new(deadline).from_err().map(|_| Loop::Continue(())))
+                    }
+                },
+            }
+        })
+    }
+
+    // Reserves the request or returns the number of ms to wait before retrying
+    fn try_acquire(&self, weight: u64, orders: u64, now: i64) -> ::std::result::Result<(), i64> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(until) = state.banned_until {
+            if until > now {
+                return Err(until - now);
+            }
+            state.banned_until = None;
+        }
+
+        let mut wait = 0;
+        for limit in &mut state.limits {
+            limit.roll(now);
+            let cost = limit.cost(weight, orders);
+            // a request heavier than the whole limit still goes through on an empty window
+            if cost > 0 && limit.used > 0 && limit.used + cost > limit.limit {
+                let reset = (limit.window + 1) * limit.interval_ms - now;
+                wait = wait.max(reset);
+            }
+        }
+        if wait > 0 {
+            return Err(wait);
+        }
+
+        for limit in &mut state.limits {
+            limit.used += limit.cost(weight, orders);
+        }
+        Ok(())
+    }
+
+    // Corrects the usage from the response headers and backs off on 429 / 418 responses
+    pub(crate) fn update(&self, status: StatusCode, headers: &HeaderMap) {
+        self.update_at(status, headers, Utc::now().timestamp_millis())
+    }
+
+    fn update_at(&self, status: StatusCode, headers: &HeaderMap, now: i64) {
+        let mut state = self.state.lock().unwrap();
+
+        for (name, value) in headers {
+            let name = name.as_str();
+            let (limit_type, interval) =
+                if let Some(interval) = name.strip_prefix("x-mbx-used-weight-") {
+                    (RateLimitType::RequestWeight, interval)
+                } else if let Some(interval) = name.strip_prefix("x-mbx-order-count-") {
+                    (RateLimitType::Orders, interval)
+                } else {
+                    continue;
+                };
+            let interval_ms = match parse_interval(interval) {
+                Some(interval_ms) => interval_ms,
+                None => continue,
+            };
+            let used = match value.to_str().ok().and_then(|v| v.parse().ok()) {
+                Some(used) => used,
+                None => continue,
+            };
+            if let Some(limit) = state
+                .limits
+                .iter_mut()
+                .find(|l| l.limit_type == limit_type && l.interval_ms == interval_ms)
+            {
+                limit.roll(now);
+                limit.used = used;
+            }
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == 418 {
+            let retry_after = headers
+                .get("retry-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(60);
+            warn!(
+                "[RateLimiter] Got {}, backing off for {} s",
+                status, retry_after
+            );
+            state.banned_until = Some(now + retry_after * 1000);
+        }
+    }
+}
+
+// Interval suffix of the usage headers, e.g. "1m" or "10s", in ms
+fn parse_interval(interval: &str) -> Option<i64> {
+    if interval.len() < 2 {
+        return None;
+    }
+    let (num, unit) = interval.split_at(interval.len() - 1);
+    let interval = match unit {
+        "s" => Interval::Second,
+        "m" => Interval::Minute,
+        "h" => Interval::Hour,
+        "d" => Interval::Day,
+        _ => return None,
+    };
+    num.parse::<i64>().ok().map(|n| n * interval.millis())
+}
+
+// Weight of a request, per the endpoint documentation.
+// Unknown endpoints count as 1; the response headers correct any mismatch.
+pub(crate) fn request_weight(endpoint: &str, query: &[(String, String)]) -> u64 {
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+    let path = endpoint.trim_start_matches("/api/v3");
+
+    match path {
+        "/depth" => match param("limit").and_then(|l| l.parse::<u64>().ok()) {
+            Some(limit) if limit <= 100 => 1,
+            Some(limit) if limit <= 500 => 5,
+            Some(limit) if limit <= 1000 => 10,
+            _ => 50,
+        },
+        "/ticker/24hr" if param("symbols").is_some() => match symbol_count(param("symbols")) {
+            0..=20 => 2,
+            21..=100 => 40,
+            _ => 80,
+        },
+        "/ticker/price" | "/ticker/bookTicker" if param("symbols").is_some() => 4,
+        "/ticker/24hr" | "/openOrders" if param("symbol").is_none() => 40,
+        "/ticker/price" | "/ticker/bookTicker" if param("symbol").is_none() => 2,
+        // 4 per symbol, at most 200
+        "/ticker" => (4 * symbol_count(param("symbols")).max(1)).min(200),
+        "/exchangeInfo" | "/allOrderList" => 10,
+        "/openOrderList" => 3,
+        "/orderList" => 2,
+        "/historicalTrades" | "/allOrders" | "/account" | "/myTrades" => 5,
+        _ => 1,
+    }
+}
+
+// Number of symbols of a `symbols` parameter, a JSON array
+fn symbol_count(symbols: Option<&str>) -> u64 {
+    symbols.map_or(0, |symbols| symbols.split(',').count() as u64)
+}
+
+// Number of orders a request places
+pub(crate) fn order_count(method: &Method, endpoint: &str) -> u64 {
+    match (method, endpoint) {
+        (&Method::POST, "/api/v3/order") => 1,
+        (&Method::POST, "/api/v3/order/oco") => 2,
+        (&Method::POST, "/api/v3/order/cancelReplace") => 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::{HeaderMap, StatusCode};
+
+    use super::{request_weight, RateLimitPolicy, RateLimiter};
+    use model::{Interval, RateLimitMsg, RateLimitType};
+
+    #[test]
+    fn acquire_within_window() {
+        let limiter = RateLimiter::new(RateLimitPolicy::Reject);
+        limiter.seed(&[RateLimitMsg {
+            rate_limit_type: RateLimitType::RequestWeight,
+            interval: Interval::Minute,
+            interval_num: 1,
+            limit: 10,
+        }]);
+
+        let now = 60_000 * 100 + 15_000;
+        assert_eq!(limiter.try_acquire(6, 0, now), Ok(()));
+        assert_eq!(limiter.try_acquire(5, 0, now), Err(45_000));
+        assert_eq!(limiter.try_acquire(4, 0, now), Ok(()));
+        assert_eq!(limiter.try_acquire(5, 0, now + 45_000), Ok(()));
+    }
+
+    #[test]
+    fn update_from_headers() {
+        let limiter = RateLimiter::new(RateLimitPolicy::Reject);
+        let now = 60_000 * 100;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", "1195".parse().unwrap());
+        limiter.update_at(StatusCode::OK, &headers, now);
+        assert_eq!(limiter.try_acquire(10, 0, now), Err(60_000));
+        assert_eq!(limiter.try_acquire(5, 0, now), Ok(()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "3".parse().unwrap());
+        limiter.update_at(StatusCode::TOO_MANY_REQUESTS, &headers, now + 60_000);
+        assert_eq!(limiter.try_acquire(1, 0, now + 61_000), Err(2_000));
+    }
+
+    #[test]
+    fn weights() {
+        let query = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            request_weight("/api/v3/depth", &query(&[("limit", "500")])),
+            5
+        );
+        assert_eq!(request_weight("/api/v3/openOrders", &query(&[])), 40);
+        assert_eq!(
+            request_weight("/api/v3/openOrders", &query(&[("symbol", "BNBBTC")])),
+            1
+        );
+        assert_eq!(request_weight("/api/v3/klines", &query(&[])), 1);
+        assert_eq!(
+            request_weight(
+                "/api/v3/ticker/price",
+                &query(&[("symbols", r#"["BNBBTC","ETHBTC"]"#)])
+            ),
+            4
+        );
+        assert_eq!(
+            request_weight(
+                "/api/v3/ticker",
+                &query(&[("symbols", r#"["BNBBTC","ETHBTC"]"#)])
+            ),
+            8
+        );
+        assert_eq!(
+            request_weight("/api/v3/ticker", &query(&[("symbol", "BNBBTC")])),
+            4
+        );
+    }
+}
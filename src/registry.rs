@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use futures::future::{ok, Either};
+use futures::{Future, IntoFuture, Stream};
+use tokio::timer::Interval;
+
+use client::Binance;
+use error::{BinanceError, Result};
+use filter::OrderValidator;
+use model::{ExchangeInfoMsg, SymbolMsg};
+
+// Exchange info indexed by symbol and by base and quote asset
+#[derive(Debug)]
+struct Index {
+    info: ExchangeInfoMsg,
+    symbols: HashMap<String, usize>,
+    by_base: HashMap<String, Vec<usize>>,
+    by_quote: HashMap<String, Vec<usize>>,
+    loaded_at: Instant,
+}
+
+impl Index {
+    fn new(info: ExchangeInfoMsg) -> Self {
+        let mut symbols = HashMap::new();
+        let mut by_base = HashMap::new();
+        let mut by_quote = HashMap::new();
+        for (i, symbol) in info.symbols.iter().enumerate() {
+            symbols.insert(symbol.symbol.clone(), i);
+            by_base
+                .entry(symbol.base_asset.clone())
+                .or_insert_with(Vec::new)
+                .push(i);
+            by_quote
+                .entry(symbol.quote_asset.clone())
+                .or_insert_with(Vec::new)
+                .push(i);
+        }
+        Index {
+            info,
+            symbols,
+            by_base,
+            by_quote,
+            loaded_at: Instant::now(),
+        }
+    }
+
+    fn symbols(&self, indices: Option<&Vec<usize>>) -> Vec<SymbolMsg> {
+        indices
+            .map(|indices| {
+                indices
+                    .iter()
+                    .map(|&i| self.info.symbols[i].clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Symbol metadata loaded once from the exchange info and shared by all its clones.
+// Lookups fail with `BinanceError::RegistryNotLoaded` before the first load, and with
+// `BinanceError::SymbolNotFound` for unknown symbols.
+#[derive(Clone)]
+pub struct SymbolRegistry {
+    binance: Binance,
+    refresh_interval: Duration,
+    index: Arc<RwLock<Option<Index>>>,
+}
+
+impl SymbolRegistry {
+    pub fn new(binance: &Binance, refresh_interval: Duration) -> Self {
+        SymbolRegistry {
+            binance: binance.clone(),
+            refresh_interval,
+            index: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    // Fetches the exchange info and replaces the index
+    pub fn load(&self) -> Result<impl Future<Item = (), Error = Error>> {
+        let index = self.index.clone();
+        Ok(self.binance.get_exchange_info()?.map(move |info| {
+            debug!("[SymbolRegistry] Loaded {} symbols", info.symbols.len());
+            *index.write().unwrap() = Some(Index::new(info));
+        }))
+    }
+
+    // Loads the exchange info if it was never loaded or is older than the refresh interval
+    pub fn refresh_if_stale(&self) -> Result<impl Future<Item = (), Error = Error>> {
+        let stale = match *self.index.read().unwrap() {
+            Some(ref index) => index.loaded_at.elapsed() >= self.refresh_interval,
+            None => true,
+        };
+        if stale {
+            Ok(Either::A(self.load()?))
+        } else {
+            Ok(Either::B(ok(())))
+        }
+    }
+
+    // Reloads the exchange info every refresh interval, to be spawned on the runtime.
+    // A failed reload is logged and the previous index kept.
+    pub fn auto_refresh(&self) -> impl Future<Item = (), Error = Error> {
+        let registry = self.clone();
+        Interval::new(
+            Instant::now() + self.refresh_interval,
+            self.refresh_interval,
+        )
+        .from_err::<Error>()
+        .for_each(move |_| {
+            let reload = match registry.load() {
+                Ok(reload) => Either::A(reload),
+                Err(e) => Either::B(Err(e).into_future()),
+            };
+            reload.or_else(|e| {
+                warn!("[SymbolRegistry] Failed to refresh exchange info: {}", e);
+                Ok(())
+            })
+        })
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.index.read().unwrap().is_some()
+    }
+
+    pub fn symbol(&self, symbol: &str) -> Result<SymbolMsg> {
+        let index = self.index.read().unwrap();
+        let index = index.as_ref().ok_or(BinanceError::RegistryNotLoaded)?;
+        let &i = index
+            .symbols
+            .get(&symbol.to_uppercase())
+            .ok_or(BinanceError::SymbolNotFound)?;
+        Ok(index.info.symbols[i].clone())
+    }
+
+    // The symbol trading `base` against `quote`, e.g. ("BNB", "BTC") for BNBBTC
+    pub fn symbol_of(&self, base: &str, quote: &str) -> Result<SymbolMsg> {
+        if !self.is_loaded() {
+            Err(BinanceError::RegistryNotLoaded)?
+        }
+        let quote = quote.to_uppercase();
+        self.with_base(base)
+            .into_iter()
+            .find(|s| s.quote_asset == quote)
+            .ok_or_else(|| BinanceError::SymbolNotFound.into())
+    }
+
+    // Symbols whose base asset is `asset`
+    pub fn with_base(&self, asset: &str) -> Vec<SymbolMsg> {
+        let index = self.index.read().unwrap();
+        index
+            .as_ref()
+            .map(|index| index.symbols(index.by_base.get(&asset.to_uppercase())))
+            .unwrap_or_default()
+    }
+
+    // Symbols whose quote asset is `asset`
+    pub fn with_quote(&self, asset: &str) -> Vec<SymbolMsg> {
+        let index = self.index.read().unwrap();
+        index
+            .as_ref()
+            .map(|index| index.symbols(index.by_quote.get(&asset.to_uppercase())))
+            .unwrap_or_default()
+    }
+
+    // Validator over the loaded exchange info, failing with `BinanceError::RegistryNotLoaded`
+    // before the first load. It is a snapshot: later refreshes do not update it.
+    pub fn order_validator(&self) -> Result<OrderValidator> {
+        let index = self.index.read().unwrap();
+        let validator = index
+            .as_ref()
+            .map(|index| OrderValidator::new(&index.info))
+            .ok_or(BinanceError::RegistryNotLoaded)?;
+        Ok(validator)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::from_str;
+
+    use super::{Index, SymbolRegistry};
+    use client::Binance;
+    use error::{BinanceError, Result};
+
+    #[test]
+    fn lookups() -> Result<()> {
+        let registry = SymbolRegistry::new(&Binance::new(), Duration::from_secs(3600));
+        match registry
+            .symbol("BNBBTC")
+            .map_err(|e| e.downcast::<BinanceError>())
+        {
+            Err(Ok(BinanceError::RegistryNotLoaded)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match registry
+            .symbol_of("BNB", "BTC")
+            .map_err(|e| e.downcast::<BinanceError>())
+        {
+            Err(Ok(BinanceError::RegistryNotLoaded)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match registry
+            .order_validator()
+            .map_err(|e| e.downcast::<BinanceError>())
+        {
+            Err(Ok(BinanceError::RegistryNotLoaded)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let symbol = |symbol: &str, base: &str, quote: &str| {
+            format!(
+                r#"{{"symbol":"{}","status":"TRADING","baseAsset":"{}","baseAssetPrecision":8,
+                    "quoteAsset":"{}","quotePrecision":8,"orderTypes":["LIMIT"],
+                    "icebergAllowed":true,"filters":[]}}"#,
+                symbol, base, quote
+            )
+        };
+        let info = from_str(&format!(
+            r#"{{"timezone":"UTC","serverTime":1565246363776,"rateLimits":[],"exchangeFilters":[],
+                "symbols":[{},{},{}]}}"#,
+            symbol("BNBBTC", "BNB", "BTC"),
+            symbol("ETHBTC", "ETH", "BTC"),
+            symbol("BNBETH", "BNB", "ETH")
+        ))?;
+        *registry.index.write().unwrap() = Some(Index::new(info));
+
+        assert_eq!(registry.symbol("bnbbtc")?.base_asset, "BNB");
+        assert_eq!(registry.symbol_of("BNB", "ETH")?.symbol, "BNBETH");
+        assert_eq!(registry.with_quote("BTC").len(), 2);
+        assert!(registry.order_validator()?.symbol("ETHBTC").is_some());
+        match registry
+            .symbol("XRPBTC")
+            .map_err(|e| e.downcast::<BinanceError>())
+        {
+            Err(Ok(BinanceError::SymbolNotFound)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+}
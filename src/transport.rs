@@ -1,51 +1,104 @@
+use std::result::Result as StdResult;
+use std::sync::Arc;
+use std::time::Instant;
+
 use chrono::Utc;
 use failure::Error;
+use futures::future::{err, loop_fn, ok, result, Either, Loop};
 use futures::{Future, Stream};
 use hex::encode as hexify;
 use hmac::{Hmac, Mac};
-use hyper::client::{HttpConnector, ResponseFuture};
-use hyper::{Body, Client, Method, Request};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Response, StatusCode};
 use hyper_tls::HttpsConnector;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use serde_json::{from_slice, to_string, to_value};
+use serde_json::{from_slice, from_value, to_string, to_value, Value};
 use sha2::Sha256;
+use tokio::timer::Delay;
+use url::form_urlencoded;
 use url::Url;
 
-use error::{BinanceError, BinanceResponse, Result};
+use clock::ServerClock;
+use config::Config;
+use error::{ApiErrorCode, BinanceError, BinanceResponse, BinanceResponseError, Result};
+use model::ServerTimeMsg;
+use ratelimit::{order_count, request_weight, RateLimiter};
 
-static BASE: &'static str = "https://www.binance.com";
-// static BASE: &'static str = "http://requestbin.fullcontact.com/199a3mf1";
 static RECV_WINDOW: usize = 5000;
+static API_V3_ORDER: &str = "/api/v3/order";
 
 #[derive(Clone)]
 pub struct Transport {
     credential: Option<(String, String)>,
     client: Client<HttpsConnector<HttpConnector>>,
+    config: Config,
+    rate_limiter: RateLimiter,
+    clock: ServerClock,
     pub recv_window: usize,
 }
 
 impl Transport {
     pub fn new() -> Self {
+        Self::with_config(None, Config::default())
+    }
+
+    pub fn with_credential(api_key: &str, api_secret: &str) -> Self {
+        Self::with_config(Some((api_key, api_secret)), Config::default())
+    }
+
+    pub fn with_config(credential: Option<(&str, &str)>, config: Config) -> Self {
         let https = HttpsConnector::new(4).unwrap();
         let client = Client::builder().build::<_, Body>(https);
 
         Transport {
-            credential: None,
-            client: client,
+            credential: credential.map(|(k, s)| (k.into(), s.into())),
+            client,
+            rate_limiter: RateLimiter::new(config.rate_limit_policy),
+            clock: ServerClock::new(config.time_sync_interval),
+            config,
             recv_window: RECV_WINDOW,
         }
     }
 
-    pub fn with_credential(api_key: &str, api_secret: &str) -> Self {
-        let https = HttpsConnector::new(4).unwrap();
-        let client = Client::builder().build::<_, Body>(https);
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-        Transport {
-            client: client,
-            credential: Some((api_key.into(), api_secret.into())),
-            recv_window: RECV_WINDOW,
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    // Server time minus local time in ms, as applied to the timestamp of signed requests
+    pub fn time_offset(&self) -> i64 {
+        self.clock.offset()
+    }
+
+    // Measures the server clock offset, compensating for half of the round trip
+    pub fn sync_time(&self) -> Result<impl Future<Item = i64, Error = Error>> {
+        let clock = self.clock.clone();
+        let sent = Utc::now().timestamp_millis();
+        Ok(self
+            .get::<ServerTimeMsg, ()>("/api/v3/time", None)?
+            .map(move |time| {
+                let received = Utc::now().timestamp_millis();
+                clock.record(sent, time.server_time as i64, received)
+            }))
+    }
+
+    // Resyncs the clock if it is due, keeping the previous offset if that fails
+    fn maybe_sync_time(&self) -> Result<impl Future<Item = (), Error = Error>> {
+        if !self.clock.claim_sync() {
+            return Ok(Either::A(ok(())));
         }
+        let clock = self.clock.clone();
+        Ok(Either::B(self.sync_time()?.then(move |result| {
+            if let Err(e) = result {
+                warn!("[ServerClock] Failed to sync server time: {}", e);
+                clock.release_sync();
+            }
+            Ok(())
+        })))
     }
 
     pub fn get<O, Q>(
@@ -156,10 +209,14 @@ impl Transport {
         Q: Serialize,
         D: Serialize,
     {
-        let url = format!("{}{}", BASE, endpoint);
-        let url = match params {
-            Some(p) => Url::parse_with_params(&url, p.to_url_query())?,
-            None => Url::parse(&url)?,
+        let query = params.map(|q| q.to_url_query()).unwrap_or_default();
+        let weight = request_weight(endpoint, &query);
+        let orders = order_count(&method, endpoint);
+        let url = format!("{}{}", self.config.rest_api_endpoint, endpoint);
+        let url = if query.is_empty() {
+            Url::parse(&url)?
+        } else {
+            Url::parse_with_params(&url, &query)?
         };
 
         let body = match data {
@@ -167,20 +224,16 @@ impl Transport {
             None => "".to_string(),
         };
 
-        let mut req = Request::builder();
-        req.method(method)
-            .uri(url.as_str())
-            .header("user-agent", "binance-rs")
-            .header("content-type", "application/x-www-form-urlencoded");
-
-        if let Ok((key, _)) = self.check_key() {
-            // This is for user stream: user stream requests need api key in the header but no signature. WEIRD
-            req.header("X-MBX-APIKEY", key);
-        }
-
-        let req = req.body(Body::from(body))?;
-
-        Ok(self.handle_response(self.client.request(req)))
+        let retry = if method == Method::GET {
+            Retry::Safe
+        } else {
+            Retry::Never
+        };
+        Ok(
+            self.send_with_retry(retry, weight, orders, move |transport| {
+                transport.unsigned(method.clone(), url.clone(), body.clone())
+            }),
+        )
     }
 
     pub fn signed_request<O, Q, D>(
@@ -195,18 +248,52 @@ impl Transport {
         Q: Serialize,
         D: Serialize,
     {
-        let query = params.map(|q| q.to_url_query()).unwrap_or_else(|| vec![]);
-        let url = format!("{}{}", BASE, endpoint);
-        let mut url = Url::parse_with_params(&url, &query)?;
-        url.query_pairs_mut()
-            .append_pair("timestamp", &Utc::now().timestamp_millis().to_string());
-        url.query_pairs_mut()
-            .append_pair("recvWindow", &self.recv_window.to_string());
+        let query = params.map(|q| q.to_url_query()).unwrap_or_default();
+        let weight = request_weight(endpoint, &query);
+        let orders = order_count(&method, endpoint);
+        let url = format!("{}{}", self.config.rest_api_endpoint, endpoint);
+        let url = Url::parse_with_params(&url, &query)?;
 
         let body = data
             .map(|data| data.to_url_query_string())
             .unwrap_or_else(|| "".to_string());
 
+        let retry = match method {
+            Method::GET => Retry::Safe,
+            Method::POST if endpoint == API_V3_ORDER => Retry::for_new_order(&body),
+            _ => Retry::Never,
+        };
+
+        // Signed after waiting for the rate limiter so that the timestamp stays within the recvWindow
+        let transport = self.clone();
+        Ok(self.maybe_sync_time()?.and_then(move |_| {
+            transport.send_with_retry(retry, weight, orders, move |transport| {
+                transport.sign(method.clone(), url.clone(), body.clone())
+            })
+        }))
+    }
+
+    fn unsigned(&self, method: Method, url: Url, body: String) -> Result<Request<Body>> {
+        let mut req = Request::builder();
+        req.method(method)
+            .uri(url.as_str())
+            .header("user-agent", "binance-rs")
+            .header("content-type", "application/x-www-form-urlencoded");
+
+        if let Ok((key, _)) = self.check_key() {
+            // This is for user stream: user stream requests need api key in the header but no signature. WEIRD
+            req.header("X-MBX-APIKEY", key);
+        }
+
+        Ok(req.body(Body::from(body))?)
+    }
+
+    fn sign(&self, method: Method, mut url: Url, body: String) -> Result<Request<Body>> {
+        url.query_pairs_mut()
+            .append_pair("timestamp", &self.clock.now().to_string());
+        url.query_pairs_mut()
+            .append_pair("recvWindow", &self.recv_window.to_string());
+
         let (key, signature) = self.signature(&url, &body)?;
         url.query_pairs_mut().append_pair("signature", &signature);
 
@@ -217,8 +304,7 @@ impl Transport {
             .header("X-MBX-APIKEY", key)
             .header("content-type", "application/x-www-form-urlencoded")
             .body(Body::from(body))?;
-
-        Ok(self.handle_response(self.client.request(req)))
+        Ok(req)
     }
 
     fn check_key(&self) -> Result<(&str, &str)> {
@@ -242,18 +328,233 @@ impl Transport {
         Ok((key, signature))
     }
 
-    fn handle_response<O: DeserializeOwned>(
+    // Sends the request made by `build` once the rate limiter allows it,
+    // and rebuilds and resends it after transient failures as allowed by `retry`.
+    fn send_with_retry<O, F>(
+        &self,
+        retry: Retry,
+        weight: u64,
+        orders: u64,
+        build: F,
+    ) -> impl Future<Item = O, Error = Error>
+    where
+        O: DeserializeOwned,
+        F: Fn(&Transport) -> Result<Request<Body>>,
+    {
+        let transport = self.clone();
+        let policy = self.config.retry_policy;
+        let build = Arc::new(build);
+
+        loop_fn(0, move |attempt| {
+            // A lost response may hide a placed order: look it up before sending it again
+            let existing: Box<dyn Future<Item = Option<Value>, Error = Error> + Send> = match retry
+            {
+                Retry::Order {
+                    ref symbol,
+                    ref client_order_id,
+                } if attempt > 0 => match transport.find_order(symbol, client_order_id) {
+                    Ok(find) => Box::new(find),
+                    Err(e) => Box::new(err(e)),
+                },
+                _ => Box::new(ok(None)),
+            };
+
+            let sender = transport.clone();
+            let build = build.clone();
+            let sent = existing.and_then(move |existing| match existing {
+                Some(order) => {
+                    debug!("[Transport] Order found, not sending it again");
+                    Either::A(result(existing_order(order)))
+                }
+                None => Either::B(
+                    sender
+                        .rate_limiter
+                        .acquire(weight, orders)
+                        .and_then(move |_| {
+                            let req = build(&sender)?;
+                            Ok(sender.send(req))
+                        })
+                        .flatten(),
+                ),
+            });
+
+            let retry = retry.clone();
+            sent.map(Loop::Break).or_else(move |e| {
+                if retry == Retry::Never || attempt >= policy.max_retries || !is_transient(&e) {
+                    return Either::A(err(e));
+                }
+                let delay = policy.delay(attempt);
+                warn!("[Transport] Request failed: {}, retrying in {:?}", e, delay);
+                Either::B(
+                    Delay::new(Instant::now() + delay)
+                        .from_err()
+                        .map(move |_| Loop::Continue(attempt + 1)),
+                )
+            })
+        })
+    }
+
+    // The order with this client order id, `None` if it does not exist
+    fn find_order(
+        &self,
+        symbol: &str,
+        client_order_id: &str,
+    ) -> Result<impl Future<Item = Option<Value>, Error = Error>> {
+        let query = vec![
+            ("symbol".to_string(), symbol.to_string()),
+            ("origClientOrderId".to_string(), client_order_id.to_string()),
+        ];
+        let weight = request_weight(API_V3_ORDER, &query);
+        let url = format!("{}{}", self.config.rest_api_endpoint, API_V3_ORDER);
+        let url = Url::parse_with_params(&url, &query)?;
+
+        let transport = self.clone();
+        Ok(self
+            .rate_limiter
+            .acquire(weight, 0)
+            .and_then(move |_| {
+                let req = transport.sign(Method::GET, url, String::new())?;
+                Ok(transport.send(req))
+            })
+            .flatten()
+            .then(|result| match result {
+                Ok(order) => Ok(Some(order)),
+                Err(e) => match e.downcast_ref::<BinanceError>() {
+                    Some(BinanceError::Api {
+                        code: ApiErrorCode::UnknownOrder,
+                        ..
+                    }) => Ok(None),
+                    _ => Err(e),
+                },
+            }))
+    }
+
+    fn send<O: DeserializeOwned>(
         &self,
-        fut: ResponseFuture,
+        req: Request<Body>,
     ) -> impl Future<Item = O, Error = Error> {
-        fut.from_err::<Error>()
-            .and_then(|resp| resp.into_body().concat2().from_err::<Error>())
-            .map(|chunk| {
-                trace!("{}", String::from_utf8_lossy(&*chunk));
-                chunk
+        let rate_limiter = self.rate_limiter.clone();
+        self.client
+            .request(req)
+            .map_err(|e| BinanceError::Transport(e.to_string()).into())
+            .and_then(move |resp: Response<Body>| {
+                rate_limiter.update(resp.status(), resp.headers());
+                let status = resp.status();
+                let retry_after = resp
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok());
+                resp.into_body()
+                    .concat2()
+                    .map_err(|e| BinanceError::Transport(e.to_string()).into())
+                    .map(move |chunk| (status, retry_after, chunk))
             })
-            .and_then(|chunk| Ok(from_slice(&chunk)?))
-            .and_then(|resp: BinanceResponse<O>| Ok(resp.to_result()?))
+            .and_then(|(status, retry_after, chunk)| {
+                trace!("{}", String::from_utf8_lossy(&chunk));
+                Ok(parse_response(status, retry_after, &chunk)?)
+            })
+    }
+}
+
+// Which failed requests can be sent again
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Retry {
+    Never,
+    Safe,
+    // A new order, identified by its client order id
+    Order {
+        symbol: String,
+        client_order_id: String,
+    },
+}
+
+impl Retry {
+    fn for_new_order(body: &str) -> Self {
+        let param = |name: &str| {
+            form_urlencoded::parse(body.as_bytes())
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+        };
+        match (param("symbol"), param("newClientOrderId")) {
+            (Some(symbol), Some(client_order_id)) => Retry::Order {
+                symbol,
+                client_order_id,
+            },
+            _ => Retry::Never,
+        }
+    }
+}
+
+fn is_transient(e: &Error) -> bool {
+    match e.downcast_ref::<BinanceError>() {
+        Some(BinanceError::Transport(_)) => true,
+        Some(BinanceError::Http { status, .. }) => *status >= 500,
+        Some(BinanceError::Api {
+            code: ApiErrorCode::Disconnected,
+            ..
+        })
+        | Some(BinanceError::Api {
+            code: ApiErrorCode::Timeout,
+            ..
+        }) => true,
+        _ => false,
+    }
+}
+
+// Response of a new order rebuilt from the order found by a query.
+// The query lacks the placement only fields: the transaction time is taken from
+// the last update and the fills, if asked for, are left empty.
+fn existing_order<O: DeserializeOwned>(mut order: Value) -> Result<O> {
+    if let Some(order) = order.as_object_mut() {
+        if !order.contains_key("transactTime") {
+            let time = order
+                .get("updateTime")
+                .or_else(|| order.get("time"))
+                .cloned()
+                .unwrap_or(Value::Null);
+            order.insert("transactTime".into(), time);
+        }
+        order.entry("fills").or_insert_with(|| Value::Array(vec![]));
+    }
+    Ok(from_value(order)?)
+}
+
+fn parse_response<O: DeserializeOwned>(
+    status: StatusCode,
+    retry_after: Option<u64>,
+    body: &[u8],
+) -> StdResult<O, BinanceError> {
+    let api_error = from_slice::<BinanceResponseError>(body);
+
+    if status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == 418 {
+        let msg = match api_error {
+            Ok(e) => e.msg,
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        };
+        return Err(BinanceError::TooManyRequests {
+            status: status.as_u16(),
+            retry_after,
+            msg,
+        });
+    }
+
+    if !status.is_success() {
+        return Err(match api_error {
+            Ok(e) => e.into(),
+            Err(_) => BinanceError::Http {
+                status: status.as_u16(),
+                body: String::from_utf8_lossy(body).into_owned(),
+            },
+        });
+    }
+
+    match from_slice::<BinanceResponse<O>>(body) {
+        Ok(resp) => Ok(resp.to_result()?),
+        Err(e) => Err(BinanceError::Decode {
+            reason: e.to_string(),
+            body: String::from_utf8_lossy(body).into_owned(),
+        }),
     }
 }
 
@@ -291,12 +592,90 @@ trait ToUrlQuery: Serialize {
 impl<S: Serialize> ToUrlQuery for S {}
 
 #[cfg(test)]
-mod test {
-    use super::Transport;
-    use error::Result;
-    use url::form_urlencoded::Serializer;
+pub(crate) mod test {
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use futures::Future;
+    use hyper::service::service_fn_ok;
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use tokio::runtime::Runtime;
+
+    use serde_json::{from_str, Value};
+
+    use super::{existing_order, parse_response, Retry, Transport};
+    use config::{Backoff, Config, RetryPolicy};
+    use error::{ApiErrorCode, BinanceError, Result};
+    use model::OrderFullMsg;
+    use url::form_urlencoded::{self, Serializer};
     use url::Url;
 
+    static ORDER: &str = r#"{"symbol":"BNBBTC","orderId":1,"orderListId":-1,"clientOrderId":"my order",
+        "price":"0.1","origQty":"1","executedQty":"0","cummulativeQuoteQty":"0",
+        "status":"NEW","timeInForce":"GTC","type":"LIMIT","side":"BUY","stopPrice":"0",
+        "icebergQty":"0","time":1499827319559,"updateTime":1499827319560,"isWorking":true}"#;
+    static PLACED: &str = r#"{"symbol":"BNBBTC","orderId":2,"orderListId":-1,"clientOrderId":"my order",
+        "transactTime":1499827319561,"price":"0.1","origQty":"1","executedQty":"0",
+        "cummulativeQuoteQty":"0","status":"NEW","timeInForce":"GTC","type":"LIMIT","side":"BUY",
+        "fills":[]}"#;
+
+    // Serves the scripted responses in order on a local port, logging the requests received
+    // with their query parameters but the signature ones. Returns a config using that port.
+    pub(crate) fn serve(
+        rt: &mut Runtime,
+        responses: Vec<(u16, &'static str)>,
+    ) -> (Config, Arc<Mutex<Vec<String>>>) {
+        let log = Arc::new(Mutex::new(vec![]));
+        let responses = Arc::new(Mutex::new(VecDeque::from(responses)));
+
+        let requests = log.clone();
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(move || {
+            let requests = requests.clone();
+            let responses = responses.clone();
+            service_fn_ok(move |req: Request<Body>| {
+                let query = req.uri().query().unwrap_or_default().as_bytes();
+                let params: Vec<_> = form_urlencoded::parse(query)
+                    .filter(|(k, _)| !["timestamp", "recvWindow", "signature"].contains(&&**k))
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                let mut request = format!("{} {}", req.method(), req.uri().path());
+                if !params.is_empty() {
+                    request = format!("{} {}", request, params.join("&"));
+                }
+                requests.lock().unwrap().push(request);
+                let (status, body) = responses.lock().unwrap().pop_front().unwrap();
+                Response::builder()
+                    .status(status)
+                    .body(Body::from(body))
+                    .unwrap()
+            })
+        });
+        let config = Config::default()
+            .set_rest_api_endpoint(format!("http://{}", server.local_addr()))
+            .set_retry_policy(RetryPolicy {
+                max_retries: 2,
+                backoff: Backoff {
+                    initial: Duration::from_millis(1),
+                    max: Duration::from_millis(1),
+                    factor: 1,
+                },
+                jitter: Duration::from_millis(0),
+            });
+        rt.spawn(server.map_err(|e| panic!("{}", e)));
+        (config, log)
+    }
+
+    fn new_order(client_order_id: Option<&str>) -> BTreeMap<&str, &str> {
+        let mut order = BTreeMap::new();
+        order.insert("symbol", "BNBBTC");
+        order.insert("side", "BUY");
+        if let Some(client_order_id) = client_order_id {
+            order.insert("newClientOrderId", client_order_id);
+        }
+        order
+    }
+
     #[test]
     fn signature_query() -> Result<()> {
         let tr = Transport::with_credential(
@@ -413,4 +792,114 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn response_errors() {
+        match parse_response::<u64>(StatusCode::BAD_GATEWAY, None, b"<html>502</html>") {
+            Err(BinanceError::Http { status, body }) => {
+                assert_eq!(status, 502);
+                assert_eq!(body, "<html>502</html>");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        match parse_response::<u64>(
+            StatusCode::BAD_REQUEST,
+            None,
+            br#"{"code":-2013,"msg":"Order does not exist."}"#,
+        ) {
+            Err(BinanceError::Api { code, .. }) => assert_eq!(code, ApiErrorCode::UnknownOrder),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        match parse_response::<u64>(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(7),
+            br#"{"code":-1003,"msg":"Too many requests."}"#,
+        ) {
+            Err(BinanceError::TooManyRequests { retry_after, .. }) => {
+                assert_eq!(retry_after, Some(7))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        match parse_response::<u64>(StatusCode::OK, None, b"[]") {
+            Err(BinanceError::Decode { body, .. }) => assert_eq!(body, "[]"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn retry_new_order() -> Result<()> {
+        assert_eq!(
+            Retry::for_new_order("symbol=BNBBTC&side=BUY&newClientOrderId=my%20order"),
+            Retry::Order {
+                symbol: "BNBBTC".into(),
+                client_order_id: "my order".into(),
+            }
+        );
+        assert_eq!(Retry::for_new_order("symbol=BNBBTC&side=BUY"), Retry::Never);
+
+        let order: OrderFullMsg = existing_order(from_str(ORDER)?)?;
+        assert_eq!(order.transact_time, 1499827319560);
+        assert!(order.fills.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn order_without_client_id_not_retried() -> Result<()> {
+        let mut rt = Runtime::new()?;
+        let (config, log) = serve(&mut rt, vec![(503, "unavailable")]);
+        let transport = Transport::with_config(Some(("key", "secret")), config);
+
+        let order = transport.signed_post::<Value, _>("/api/v3/order", Some(new_order(None)))?;
+        match rt.block_on(order) {
+            Err(e) => match e.downcast_ref::<BinanceError>() {
+                Some(BinanceError::Http { status, .. }) => assert_eq!(*status, 503),
+                _ => panic!("unexpected error {}", e),
+            },
+            Ok(order) => panic!("unexpected order {}", order),
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["POST /api/v3/order"]);
+        Ok(())
+    }
+
+    #[test]
+    fn order_looked_up_before_resending() -> Result<()> {
+        let mut rt = Runtime::new()?;
+
+        // The first attempt went through: the order found is returned, not sent again
+        let (config, log) = serve(&mut rt, vec![(503, "unavailable"), (200, ORDER)]);
+        let transport = Transport::with_config(Some(("key", "secret")), config);
+        let order = transport
+            .signed_post::<OrderFullMsg, _>("/api/v3/order", Some(new_order(Some("my order"))))?;
+        assert_eq!(rt.block_on(order)?.order_id, 1);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "POST /api/v3/order",
+                "GET /api/v3/order symbol=BNBBTC&origClientOrderId=my order"
+            ]
+        );
+
+        // It did not: the order is sent again
+        let unknown = r#"{"code":-2013,"msg":"Order does not exist."}"#;
+        let (config, log) = serve(
+            &mut rt,
+            vec![(503, "unavailable"), (400, unknown), (200, PLACED)],
+        );
+        let transport = Transport::with_config(Some(("key", "secret")), config);
+        let order = transport
+            .signed_post::<OrderFullMsg, _>("/api/v3/order", Some(new_order(Some("my order"))))?;
+        assert_eq!(rt.block_on(order)?.order_id, 2);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "POST /api/v3/order",
+                "GET /api/v3/order symbol=BNBBTC&origClientOrderId=my order",
+                "POST /api/v3/order"
+            ]
+        );
+        Ok(())
+    }
 }